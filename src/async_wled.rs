@@ -0,0 +1,304 @@
+use reqwest;
+use reqwest::{Client, Response};
+use reqwest::Url;
+
+use crate::builder::{join_endpoint, Timeouts, WledBuilder};
+use crate::errors::WledJsonApiError;
+use crate::structures::cfg::Cfg;
+use crate::structures::effects::Effects;
+use crate::structures::info::Info;
+use crate::structures::live::Live;
+use crate::structures::net::Net;
+use crate::structures::nodes::{Node, Nodes};
+use crate::structures::palettes::Palettes;
+use crate::structures::state::{Playlist, Seg, State};
+use crate::structures::state_info::StateInfo;
+use crate::structures::full::Full;
+use crate::structures::response::FlushResponse;
+use crate::structures::presets::{Presets, PresetSave};
+use crate::structures::relative::RelativeValue;
+use crate::structures::color::Color;
+use crate::structures::individual::{IndividualLeds, DEFAULT_LEDS_PER_REQUEST};
+
+
+/// Async twin of [`crate::wled::Wled`], built on `reqwest::Client` instead of the blocking one.
+/// Same fields, same methods, just `.await` them. Only available with the `async` feature.
+#[derive(Debug)]
+pub struct AsyncWled {
+    pub effects: Option<Effects>,
+    pub palettes: Option<Palettes>,
+    pub state: Option<State>,
+    pub info: Option<Info>,
+    pub cfg: Option<Cfg>,
+    pub live: Option<Live>,
+    pub nodes: Option<Nodes>,
+    pub net: Option<Net>,
+    pub presets: Option<Presets>,
+    pub client: Client,
+    /// base url of the WLED (including any reverse proxy path), always ends in '/'.
+    pub url: Url,
+    /// per request class timeouts, see `WledBuilder`
+    pub timeouts: Timeouts,
+}
+
+impl AsyncWled{
+
+    /// shorthand for `WledBuilder::new(url).build_async().await`
+    pub async fn try_from_url(url: &Url) -> Result<AsyncWled, WledJsonApiError> {
+        WledBuilder::new(url).build_async().await
+    }
+
+    /// `try_from_url` on a node from another WLEDs `json/nodes`, see `Node::url`
+    pub async fn try_from_node(node: &Node) -> Result<AsyncWled, WledJsonApiError> {
+        AsyncWled::try_from_url(&node.url()?).await
+    }
+
+    pub fn builder(url: &Url) -> WledBuilder {
+        WledBuilder::new(url)
+    }
+
+    /// full url of an endpoint like "json/state", taking the base path into account
+    pub fn endpoint(&self, path: &str) -> Result<Url, WledJsonApiError> {
+        join_endpoint(&self.url, path)
+    }
+
+
+    pub async fn flush_state(&self) -> Result<Response, WledJsonApiError> {
+
+        match &self.state{
+            Some(s) => {
+                let packet: String = s.try_into()?;
+                Ok(self.client
+                    .post(self.endpoint("json/state")?)
+                    .timeout(self.timeouts.flush)
+                    .body(packet)
+                    .send()
+                    .await?)
+            }
+            None => Err(WledJsonApiError::FlushNone)
+        }
+    }
+
+
+    /// Like `flush_state`, but sets `v` so WLED answers with the full state it ended up in,
+    /// and stores that in `self.state`. WLED error payloads come back as `WledJsonApiError::WledError`.
+    ///
+    /// `v` itself is only set on what's sent, the cached state gets replaced by WLEDs answer anyway.
+    pub async fn flush_state_verbose(&mut self) -> Result<FlushResponse, WledJsonApiError> {
+        let packet: String = match &self.state{
+            Some(s) => {
+                let mut verbose = s.clone();
+                verbose.v = Some(true);
+                (&verbose).try_into()?
+            }
+            None => return Err(WledJsonApiError::FlushNone)
+        };
+        let response = self.post_state(packet).await?;
+        if let FlushResponse::State(s) = &response {
+            self.state = Some(s.clone());
+        }
+        Ok(response)
+    }
+
+    /// POSTs an already serialized packet to `json/state` and parses what comes back
+    async fn post_state(&self, packet: String) -> Result<FlushResponse, WledJsonApiError> {
+        let response = self.client
+            .post(self.endpoint("json/state")?)
+            .timeout(self.timeouts.flush)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(packet)
+            .send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        FlushResponse::from_http(status, &text)
+    }
+
+
+    /// same warning as the blocking version, nothing stops you from sending a config that bricks your setup.
+    pub async fn flush_config(&self) -> Result<Response, WledJsonApiError> {
+
+        match &self.cfg{
+            Some(s) => {
+                let packet: String = s.try_into()?;
+                Ok(self.client
+                    .post(self.endpoint("json/cfg")?)
+                    .timeout(self.timeouts.flush)
+                    .body(packet)
+                    .send()
+                    .await?)
+            }
+            None => Err(WledJsonApiError::FlushNone)
+        }
+    }
+
+    /// GETs `path` and hands back the body text, shared by all the `get_*_from_wled` below
+    async fn get_text(&self, path: &str) -> Result<String, WledJsonApiError> {
+        Ok(self.client
+            .get(self.endpoint(path)?)
+            .timeout(self.timeouts.fetch)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .await?
+            .text()
+            .await?)
+    }
+
+    pub async fn get_effects_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.effects = Some(Effects::try_from(&*self.get_text("json/eff").await?)?);
+        Ok(())
+    }
+
+    pub async fn get_info_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.info = Some(Info::try_from(&*self.get_text("json/info").await?)?);
+        Ok(())
+    }
+
+    pub async fn get_state_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.state = Some(State::try_from(&*self.get_text("json/state").await?)?);
+        Ok(())
+    }
+
+    pub async fn get_cfg_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.cfg = Some(Cfg::try_from(&*self.get_text("json/cfg").await?)?);
+        Ok(())
+    }
+
+    pub async fn get_net_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.net = Some(Net::try_from(&*self.get_text("json/net").await?)?);
+        Ok(())
+    }
+
+    pub async fn get_nodes_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.nodes = Some(Nodes::try_from(&*self.get_text("json/nodes").await?)?);
+        Ok(())
+    }
+
+    pub async fn get_palettes_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.palettes = Some(Palettes::try_from(&*self.get_text("json/pal").await?)?);
+        Ok(())
+    }
+
+    /// fills `state` and `info` from `/json/si`, one request instead of two
+    pub async fn get_state_info_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        let state_info = StateInfo::try_from(&*self.get_text("json/si").await?)?;
+        self.state = Some(state_info.state);
+        self.info = Some(state_info.info);
+        Ok(())
+    }
+
+    /// fills `state`, `info`, `effects` and `palettes` from `/json`, one request instead of four
+    pub async fn get_full_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        let full = Full::try_from(&*self.get_text("json").await?)?;
+        self.state = Some(full.state);
+        self.info = Some(full.info);
+        self.effects = Some(full.effects);
+        self.palettes = Some(full.palettes);
+        Ok(())
+    }
+
+    pub async fn get_live_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.live = Some(Live::try_from(&*self.get_text("json/live").await?)?);
+        Ok(())
+    }
+
+    /// downloads `/presets.json` into `self.presets`
+    pub async fn get_presets_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.presets = Some(Presets::try_from(&*self.get_text("presets.json").await?)?);
+        Ok(())
+    }
+
+    /// Saves (or overwrites) a preset, see the constructors on `PresetSave`.
+    /// WLED writes the file in the background, so `presets.json` can lag behind for a moment.
+    pub async fn save_preset(&self, save: &PresetSave) -> Result<FlushResponse, WledJsonApiError> {
+        self.post_state(save.try_into()?).await
+    }
+
+    /// WLED can't rename in place, so this saves the preset again under the new name.
+    /// Uses `self.presets`, fetching it first if it's None.
+    pub async fn rename_preset(&mut self, id: u8, name: &str) -> Result<FlushResponse, WledJsonApiError> {
+        if self.presets.is_none() {
+            self.get_presets_from_wled().await?;
+        }
+        let preset = self.presets.as_ref()
+            .and_then(|p| p.get(id))
+            .ok_or(WledJsonApiError::MissingKey)?;
+        let save = PresetSave::from_preset(id, name, preset);
+        let response = self.save_preset(&save).await?;
+        if let Some(p) = self.presets.as_mut().and_then(|p| p.0.get_mut(&id)) {
+            p.name = Some(name.to_string());
+        }
+        Ok(response)
+    }
+
+    pub async fn delete_preset(&mut self, id: u8) -> Result<FlushResponse, WledJsonApiError> {
+        let response = self.post_state(serde_json::json!({"pdel": id}).to_string()).await?;
+        if let Some(p) = self.presets.as_mut() {
+            p.0.remove(&id);
+        }
+        Ok(response)
+    }
+
+    pub async fn apply_preset(&self, id: u8) -> Result<FlushResponse, WledJsonApiError> {
+        let state = State{ps: Some(RelativeValue::Absolute(id as i32)), ..Default::default()};
+        self.post_state((&state).try_into()?).await
+    }
+
+    /// starts `playlist` right away, without saving it anywhere
+    pub async fn apply_playlist(&self, playlist: &Playlist) -> Result<FlushResponse, WledJsonApiError> {
+        let state = State{playlist: Some(playlist.clone()), ..Default::default()};
+        self.post_state((&state).try_into()?).await
+    }
+
+    /// saves `playlist` as preset `id`, apply it later with `apply_preset`
+    pub async fn save_playlist_preset(&self, id: u8, name: &str, playlist: &Playlist) -> Result<FlushResponse, WledJsonApiError> {
+        self.save_preset(&PresetSave::playlist(id, name, playlist)).await
+    }
+
+    /// Sets every LED of segment `seg_id` to `colors` (LED 0 of the segment first) through the `i` array.
+    /// Long frames are split into several requests of `DEFAULT_LEDS_PER_REQUEST` LEDs so WLEDs JSON buffer
+    /// doesn't overflow, use `set_individual_leds_chunked` to pick the size yourself.
+    pub async fn set_individual_leds(&self, seg_id: u8, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        self.set_individual_leds_chunked(seg_id, colors, DEFAULT_LEDS_PER_REQUEST).await
+    }
+
+    pub async fn set_individual_leds_chunked(&self, seg_id: u8, colors: &[Color], per_request: usize) -> Result<(), WledJsonApiError> {
+        for chunk in IndividualLeds::chunked(0, colors, per_request)? {
+            let state = State{
+                seg: Some(vec![Seg{id: Some(seg_id as i16), i: Some(chunk.hex()), ..Default::default()}]),
+                ..Default::default()
+            };
+            self.post_state((&state).try_into()?).await?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::TestServer;
+    use super::*;
+
+    #[tokio::test]
+    async fn it_works() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/json/cfg" => (200, "{}".to_string()),
+            "/json/state" if r.method == "GET" => (200, r#"{"on":true,"bri":77}"#.to_string()),
+            "/json/state" => (200, r#"{"success":true}"#.to_string()),
+            "/json/info" => (200, r#"{"ver":"0.14.0","brand":"WLED"}"#.to_string()),
+            _ => (404, String::new()),
+        });
+
+        let mut wled = AsyncWled::try_from_url(&server.url()).await.unwrap();
+        wled.get_state_from_wled().await.unwrap();
+        wled.get_info_from_wled().await.unwrap();
+        assert_eq!(wled.state.as_ref().unwrap().bri, Some(77));
+        assert_eq!(wled.info.as_ref().unwrap().brand.as_deref(), Some("WLED"));
+
+        wled.state = Some(State { on: Some(false), ..Default::default() });
+        let response = wled.flush_state().await.unwrap();
+        assert!(response.status().is_success());
+        let posted = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(posted.body, r#"{"on":false}"#);
+    }
+}
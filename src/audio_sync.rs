@@ -0,0 +1,258 @@
+//! Audio sync for WLEDs AudioReactive usermod, so the audio effects (GEQ, Freqwave, Gravimeter...)
+//! can run off your own audio instead of a microphone on the ESP.
+//! Set the device to receive audio sync (Usermods > AudioReactive > Sync) and send it packets.
+//!
+//! Packet layout is the v2 one (header "00002", 44 bytes), `audioSyncPacket` in audio_reactive.h.
+
+use std::f32::consts::PI;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+use crate::errors::WledJsonApiError;
+
+
+/// AudioReactive default sync port
+pub const AUDIO_SYNC_PORT: u16 = 11988;
+/// where AudioReactive listens by default
+pub const AUDIO_SYNC_MULTICAST: Ipv4Addr = Ipv4Addr::new(239, 0, 0, 1);
+pub const AUDIO_SYNC_PACKET_LEN: usize = 44;
+
+const HEADER_V2: &[u8; 6] = b"00002\0";
+
+/// Edges of the 16 GEQ channels in Hz. Same split as AudioReactive
+/// (22050 Hz sampling, 512 point FFT, so about 43 Hz per bin).
+const GEQ_BANDS: [f32; 17] = [
+    43.0, 86.0, 129.0, 216.0, 301.0, 430.0, 560.0, 818.0, 1120.0,
+    1421.0, 1895.0, 2412.0, 3015.0, 3704.0, 4479.0, 7106.0, 9259.0,
+];
+
+
+/// One v2 audio sync packet. Floats are little endian, like the ESP sends them.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct AudioSyncPacket {
+    /// sound pressure, 8 bit integer and 8 bit fraction. optional, 0 if unknown
+    pub pressure: [u8; 2],
+    /// 0 to 255; volume of this frame
+    pub sample_raw: f32,
+    /// 0 to 255; smoothed volume, what most effects use
+    pub sample_smth: f32,
+    /// 0 for no peak, 1 or more if a peak (beat) was detected
+    pub sample_peak: u8,
+    /// goes up by one with every packet, used to spot duplicates and packets out of order
+    pub frame_counter: u8,
+    /// 0 to 255; the 16 GEQ channels, bass first
+    pub fft_result: [u8; 16],
+    /// magnitude of the strongest frequency
+    pub fft_magnitude: f32,
+    /// frequency of the strongest frequency in Hz
+    pub fft_major_peak: f32,
+}
+
+impl AudioSyncPacket {
+    pub fn encode(&self) -> [u8; AUDIO_SYNC_PACKET_LEN] {
+        let mut out = [0u8; AUDIO_SYNC_PACKET_LEN];
+        out[0..6].copy_from_slice(HEADER_V2);
+        out[6..8].copy_from_slice(&self.pressure);
+        out[8..12].copy_from_slice(&self.sample_raw.to_le_bytes());
+        out[12..16].copy_from_slice(&self.sample_smth.to_le_bytes());
+        out[16] = self.sample_peak;
+        out[17] = self.frame_counter;
+        out[18..34].copy_from_slice(&self.fft_result);
+        // 34 and 35 are reserved
+        out[36..40].copy_from_slice(&self.fft_magnitude.to_le_bytes());
+        out[40..44].copy_from_slice(&self.fft_major_peak.to_le_bytes());
+        out
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<AudioSyncPacket, WledJsonApiError> {
+        if packet.len() < AUDIO_SYNC_PACKET_LEN || &packet[0..5] != b"00002" {
+            return Err(WledJsonApiError::UnexpectedResponse("not a v2 audio sync packet".to_string()));
+        }
+        let f = |at: usize| f32::from_le_bytes([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]]);
+        let mut p = AudioSyncPacket{
+            pressure: [packet[6], packet[7]],
+            sample_raw: f(8),
+            sample_smth: f(12),
+            sample_peak: packet[16],
+            frame_counter: packet[17],
+            fft_magnitude: f(36),
+            fft_major_peak: f(40),
+            ..Default::default()
+        };
+        p.fft_result.copy_from_slice(&packet[18..34]);
+        Ok(p)
+    }
+}
+
+
+/// Turns plain sample buffers into `AudioSyncPacket`s.
+///
+/// Keeps the smoothed volume between calls, so hand it consecutive buffers (about 20 to 50 per second).
+/// The frequency analysis is a plain DFT over the GEQ bins, fine for the few hundred samples a frame has.
+#[derive(Debug, Clone)]
+pub struct AudioAnalyzer {
+    /// of the buffers passed to `analyze`, in Hz
+    pub sample_rate: u32,
+
+    /// 0 to 1; how much of the last smoothed volume is kept each frame
+    pub smoothing: f32,
+
+    /// a frame counts as a peak when its volume is this many times the smoothed volume
+    pub peak_threshold: f32,
+
+    /// frames below this volume (0 to 255) are never peaks
+    pub peak_min_volume: f32,
+
+    /// GEQ channels are scaled from this many dB below full scale (0) up to full scale (255)
+    pub fft_range_db: f32,
+
+    sample_smth: f32,
+}
+
+impl AudioAnalyzer {
+    pub fn new(sample_rate: u32) -> AudioAnalyzer {
+        AudioAnalyzer{
+            sample_rate,
+            smoothing: 0.8,
+            peak_threshold: 1.6,
+            peak_min_volume: 16.0,
+            fft_range_db: 60.0,
+            sample_smth: 0.0,
+        }
+    }
+
+    /// `samples` from -1 to 1, mono. Only the last 1024 are used for the frequency analysis.
+    /// `frame_counter` is left at 0, `AudioSyncSender` fills it in.
+    pub fn analyze(&mut self, samples: &[f32]) -> AudioSyncPacket {
+        let rms = if samples.is_empty() { 0.0 } else { (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt() };
+        let sample_raw = (rms * 255.0).min(255.0);
+        self.sample_smth = self.sample_smth * self.smoothing + sample_raw * (1.0 - self.smoothing);
+        let peak = sample_raw >= self.peak_min_volume && sample_raw > self.sample_smth * self.peak_threshold;
+
+        let window = &samples[samples.len().saturating_sub(1024)..];
+        let n = window.len();
+        let mut fft_result = [0u8; 16];
+        let (mut fft_magnitude, mut fft_major_peak) = (0.0f32, 0.0f32);
+        if n > 0 {
+            let bin_hz = self.sample_rate as f32 / n as f32;
+            let max_bin = n / 2;
+            for (band, edges) in GEQ_BANDS.windows(2).enumerate() {
+                let lo = ((edges[0] / bin_hz).round() as usize).max(1);
+                let hi = ((edges[1] / bin_hz).round() as usize).max(lo + 1).min(max_bin + 1);
+                let mut sum = 0.0;
+                for k in lo..hi {
+                    let m = dft_magnitude(window, k);
+                    sum += m;
+                    if m > fft_magnitude {
+                        fft_magnitude = m;
+                        fft_major_peak = k as f32 * bin_hz;
+                    }
+                }
+                let amplitude = if hi > lo { sum / (hi - lo) as f32 } else { 0.0 };
+                let db = 20.0 * amplitude.max(1e-9).log10();
+                fft_result[band] = ((db + self.fft_range_db) / self.fft_range_db * 255.0).clamp(0.0, 255.0) as u8;
+            }
+        }
+
+        AudioSyncPacket{
+            sample_raw,
+            sample_smth: self.sample_smth,
+            sample_peak: peak as u8,
+            fft_result,
+            fft_magnitude,
+            fft_major_peak,
+            ..Default::default()
+        }
+    }
+}
+
+/// amplitude of bin `k`, scaled so a full scale sine comes out as 1
+fn dft_magnitude(samples: &[f32], k: usize) -> f32 {
+    let n = samples.len() as f32;
+    let step = 2.0 * PI * k as f32 / n;
+    let (mut re, mut im) = (0.0f32, 0.0f32);
+    for (i, s) in samples.iter().enumerate() {
+        let angle = step * i as f32;
+        re += s * angle.cos();
+        im -= s * angle.sin();
+    }
+    (re * re + im * im).sqrt() * 2.0 / n
+}
+
+
+/// Sends audio sync packets, to 239.0.0.1:11988 unless told otherwise
+#[derive(Debug)]
+pub struct AudioSyncSender {
+    pub socket: UdpSocket,
+    pub target: SocketAddr,
+    pub analyzer: AudioAnalyzer,
+    frame_counter: u8,
+}
+
+impl AudioSyncSender{
+
+    /// multicast to the AudioReactive default group and port
+    pub fn new(sample_rate: u32) -> Result<AudioSyncSender, WledJsonApiError> {
+        AudioSyncSender::with_target(SocketAddr::new(IpAddr::V4(AUDIO_SYNC_MULTICAST), AUDIO_SYNC_PORT), sample_rate)
+    }
+
+    /// to a different group/port, or straight to one device
+    pub fn with_target(target: SocketAddr, sample_rate: u32) -> Result<AudioSyncSender, WledJsonApiError> {
+        let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        Ok(AudioSyncSender{
+            socket: UdpSocket::bind(local)?,
+            target,
+            analyzer: AudioAnalyzer::new(sample_rate),
+            frame_counter: 0,
+        })
+    }
+
+    /// sends a packet you made yourself, the frame counter gets filled in
+    pub fn send(&mut self, packet: &AudioSyncPacket) -> Result<(), WledJsonApiError> {
+        let mut packet = *packet;
+        self.frame_counter = self.frame_counter.wrapping_add(1);
+        packet.frame_counter = self.frame_counter;
+        self.socket.send_to(&packet.encode(), self.target)?;
+        Ok(())
+    }
+
+    /// runs `samples` through `analyzer` and sends the result
+    pub fn send_samples(&mut self, samples: &[f32]) -> Result<AudioSyncPacket, WledJsonApiError> {
+        let packet = self.analyzer.analyze(samples);
+        self.send(&packet)?;
+        Ok(packet)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let sine: Vec<f32> = (0..1024).map(|i| (2.0 * PI * 1000.0 * i as f32 / 22050.0).sin() * 0.5).collect();
+        let mut analyzer = AudioAnalyzer::new(22050);
+        let packet = analyzer.analyze(&sine);
+        // 1 kHz is in channel 7 (818 to 1120 Hz)
+        let loudest = (0..16).max_by_key(|b| packet.fft_result[*b]).unwrap();
+        assert_eq!(loudest, 7);
+        assert!((packet.fft_major_peak - 1000.0).abs() < 22050.0 / 1024.0);
+        assert!((packet.sample_raw - 0.5 / 2f32.sqrt() * 255.0).abs() < 1.0);
+        assert_eq!(packet.sample_peak, 1);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sender = AudioSyncSender::with_target(receiver.local_addr().unwrap(), 22050).unwrap();
+        sender.send(&packet).unwrap();
+
+        let mut buf = [0u8; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(len, 44);
+        assert_eq!(&buf[..6], b"00002\0");
+        assert_eq!(&buf[8..12], &packet.sample_raw.to_le_bytes());
+        assert_eq!(buf[17], 1);
+        let decoded = AudioSyncPacket::decode(&buf[..len]).unwrap();
+        assert_eq!(decoded, AudioSyncPacket{ frame_counter: 1, ..packet });
+    }
+}
@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Url;
+
+use crate::errors::WledJsonApiError;
+use crate::wled::Wled;
+#[cfg(feature = "async")]
+use crate::async_wled::AsyncWled;
+
+
+/// How long each kind of request is allowed to take.
+/// every field defaults to the 5000 ms that `Wled::try_from_url` always used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    /// time allowed to establish the TCP connection, applies to every request
+    pub connect: Duration,
+
+    /// the optional `json/cfg` GET done when building (see `WledBuilder::probe`)
+    pub probe: Duration,
+
+    /// all the `get_*_from_wled` calls
+    pub fetch: Duration,
+
+    /// all the `flush_*` calls (POSTs)
+    pub flush: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Duration::from_millis(5000u64),
+            probe: Duration::from_millis(5000u64),
+            fetch: Duration::from_millis(5000u64),
+            flush: Duration::from_millis(5000u64),
+        }
+    }
+}
+
+
+/// Configures and builds a [`Wled`] (or an `AsyncWled` with the `async` feature).
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use reqwest::Url;
+/// use wled_json_api_library::builder::WledBuilder;
+///
+/// let wled = WledBuilder::new(&Url::parse("http://proxy.local/wled/kitchen/").unwrap())
+///     .port(8080)
+///     .fetch_timeout(Duration::from_millis(800))
+///     .user_agent("my-controller/1.0")
+///     .probe(false)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct WledBuilder {
+    url: Url,
+    port: Option<u16>,
+    base_path: Option<String>,
+    timeouts: Timeouts,
+    user_agent: Option<String>,
+    headers: HeaderMap,
+    gzip: bool,
+    probe: bool,
+    client: Option<reqwest::blocking::Client>,
+}
+
+impl WledBuilder{
+
+    /// Starts from `url`. Its path is kept as the base path, so `http://host/wled/` talks to
+    /// `http://host/wled/json/state` and so on.
+    pub fn new(url: &Url) -> WledBuilder {
+        WledBuilder{
+            url: url.clone(),
+            port: None,
+            base_path: None,
+            timeouts: Timeouts::default(),
+            user_agent: None,
+            headers: HeaderMap::new(),
+            gzip: true,
+            probe: true,
+            client: None,
+        }
+    }
+
+    /// overrides the port in the url
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Path prefix every endpoint is put behind, for WLEDs sitting behind a reverse proxy.
+    /// Replaces whatever path the url came with.
+    pub fn base_path(mut self, base_path: &str) -> Self {
+        self.base_path = Some(base_path.to_string());
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = timeout;
+        self
+    }
+
+    pub fn probe_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.probe = timeout;
+        self
+    }
+
+    pub fn fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.fetch = timeout;
+        self
+    }
+
+    pub fn flush_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.flush = timeout;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// extra header sent with every request (auth for a proxy, for example)
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// adds all of `headers` to the ones sent with every request
+    pub fn headers(mut self, headers: HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// ask for gzipped responses. on by default
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// If true (default) `build` GETs `json/cfg` and fails if the WLED doesn't answer with 200.
+    /// If false nothing is sent until you call something.
+    pub fn probe(mut self, probe: bool) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Use `client` instead of building a new one, so many handles share its connection pool.
+    /// The connect timeout, user agent, headers and gzip setting are then whatever `client` was built with.
+    pub(crate) fn client(mut self, client: reqwest::blocking::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// the url every endpoint gets joined onto, always ends in a '/'
+    fn base_url(&self) -> Result<Url, WledJsonApiError> {
+        let mut url = self.url.clone();
+        if let Some(p) = self.port {
+            url.set_port(Some(p)).map_err(|_|{WledJsonApiError::UnableToAddPortToURL})?;
+        }
+        if let Some(b) = &self.base_path {
+            url.set_path(b);
+        }
+        if !url.path().ends_with('/') {
+            let path = format!("{}/", url.path());
+            url.set_path(&path);
+        }
+        url.set_query(None);
+        url.set_fragment(None);
+        Ok(url)
+    }
+
+    pub fn build(self) -> Result<Wled, WledJsonApiError> {
+        let url = self.base_url()?;
+        // kept on the `Wled` too, for requests that don't go through the client (the websocket handshake)
+        let mut headers = self.headers.clone();
+        if let Some(a) = &self.user_agent {
+            let value = HeaderValue::from_str(a).map_err(|_| WledJsonApiError::InvalidValue(format!("user agent {a:?}")))?;
+            headers.insert(reqwest::header::USER_AGENT, value);
+        }
+        let mut client_builder = reqwest::blocking::ClientBuilder::new()
+            .gzip(self.gzip)
+            .connect_timeout(self.timeouts.connect)
+            .default_headers(self.headers);
+        if let Some(a) = self.user_agent {
+            client_builder = client_builder.user_agent(a);
+        }
+
+        let client = match self.client {
+            Some(c) => c,
+            None => client_builder.build()?,
+        };
+
+        let wled = Wled{
+            effects: None,
+            palettes: None,
+            state: None,
+            info: None,
+            cfg: None,
+            live: None,
+            nodes: None,
+            net: None,
+            presets: None,
+            client,
+            url,
+            timeouts: self.timeouts,
+            headers,
+        };
+
+        if self.probe {
+            let response = wled.client
+                .get(wled.endpoint("json/cfg")?)
+                .timeout(wled.timeouts.probe)
+                .send()?;
+            if response.status() != reqwest::StatusCode::OK {
+                return Err(WledJsonApiError::HttpError(response.status()));
+            }
+        }
+        Ok(wled)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn build_async(self) -> Result<AsyncWled, WledJsonApiError> {
+        let url = self.base_url()?;
+        let mut client_builder = reqwest::ClientBuilder::new()
+            .gzip(self.gzip)
+            .connect_timeout(self.timeouts.connect)
+            .default_headers(self.headers);
+        if let Some(a) = self.user_agent {
+            client_builder = client_builder.user_agent(a);
+        }
+
+        let wled = AsyncWled{
+            effects: None,
+            palettes: None,
+            state: None,
+            info: None,
+            cfg: None,
+            live: None,
+            nodes: None,
+            net: None,
+            presets: None,
+            client: client_builder.build()?,
+            url,
+            timeouts: self.timeouts,
+        };
+
+        if self.probe {
+            let response = wled.client
+                .get(wled.endpoint("json/cfg")?)
+                .timeout(wled.timeouts.probe)
+                .send()
+                .await?;
+            if response.status() != reqwest::StatusCode::OK {
+                return Err(WledJsonApiError::HttpError(response.status()));
+            }
+        }
+        Ok(wled)
+    }
+}
+
+
+/// joins an endpoint like "json/state" onto a base url from `WledBuilder` (which always ends in '/')
+pub(crate) fn join_endpoint(base: &Url, path: &str) -> Result<Url, WledJsonApiError> {
+    base.join(path).map_err(|_| WledJsonApiError::InvalidUrl(format!("{base} + {path}")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::TestServer;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/proxy/wled/json/info" => (200, r#"{"brand":"WLED"}"#.to_string()),
+            _ => (404, String::new()),
+        });
+
+        let mut wled = WledBuilder::new(&Url::parse("http://127.0.0.1/").unwrap())
+            .port(server.addr.port())
+            .base_path("/proxy/wled")
+            .user_agent("fixture-controller")
+            .header(HeaderName::from_static("x-token"), HeaderValue::from_static("hunter2"))
+            .probe(false)
+            .build()
+            .unwrap();
+        // lazy, so nothing has been sent yet
+        assert!(server.requests().is_empty());
+        assert_eq!(wled.url.path(), "/proxy/wled/");
+
+        wled.get_info_from_wled().unwrap();
+        assert_eq!(wled.info.as_ref().unwrap().brand.as_deref(), Some("WLED"));
+        let r = &server.requests()[0];
+        assert_eq!(r.header("user-agent"), Some("fixture-controller"));
+        assert_eq!(r.header("x-token"), Some("hunter2"));
+
+        assert_eq!(wled.endpoint("json/state").unwrap().path(), "/proxy/wled/json/state");
+        // a url that can't be a base is an error, not a request to the base url
+        assert!(matches!(join_endpoint(&Url::parse("mailto:wled@example.com").unwrap(), "json/state"), Err(WledJsonApiError::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn probe_fails_on_bad_status() {
+        let server = TestServer::start(|_| (404, String::new()));
+        let result = WledBuilder::new(&server.url()).build();
+        assert!(matches!(result, Err(WledJsonApiError::HttpError(s)) if s == reqwest::StatusCode::NOT_FOUND));
+        assert_eq!(server.requests()[0].path, "/json/cfg");
+    }
+}
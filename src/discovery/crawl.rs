@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::discovery::{confirm, confirm_each, dedup_by_mac, pool, DEFAULT_CONCURRENCY, DEFAULT_CONFIRM_TIMEOUT};
+use crate::errors::WledJsonApiError;
+use crate::wled::Wled;
+
+
+/// Finds WLEDs through the node lists they keep of each other (`json/nodes`, Settings > Sync > Instance List).
+///
+/// Starts from one device, and asks every WLED it finds for its nodes until no new ones turn up.
+/// Only WLEDs with the instance list turned on show up in other devices lists.
+///
+/// ```no_run
+/// use reqwest::Url;
+/// use wled_json_api_library::discovery::crawl::NodeCrawler;
+///
+/// let crawl = NodeCrawler::default().crawl(&Url::parse("http://192.168.1.40/").unwrap()).unwrap();
+/// for (url, e) in &crawl.errors {
+///     println!("{url} is in a node list but: {e}");
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NodeCrawler {
+    /// per device, for both `json/info` and `json/nodes`
+    pub timeout: Duration,
+    /// devices probed at once
+    pub concurrency: usize,
+    /// stop once this many WLEDs were found, in case something loops forever
+    pub max_devices: usize,
+    /// Port every node is asked on. Nodes only come with an IP, so None is WLEDs port 80 (`Node::url`),
+    /// set it if the devices sit behind something that moved them all to another port.
+    pub port: Option<u16>,
+}
+
+impl Default for NodeCrawler {
+    fn default() -> Self {
+        NodeCrawler{
+            timeout: DEFAULT_CONFIRM_TIMEOUT,
+            concurrency: DEFAULT_CONCURRENCY,
+            max_devices: 256,
+            port: None,
+        }
+    }
+}
+
+/// What `NodeCrawler::crawl` found
+#[derive(Debug)]
+pub struct Crawl {
+    /// every WLED reachable from the seed, the seed first, with `info` and `nodes` fetched and de-duplicated by MAC
+    pub wleds: Vec<Wled>,
+    /// nodes that were listed but didn't answer, or whose own node list couldn't be fetched
+    pub errors: Vec<(Url, WledJsonApiError)>,
+}
+
+impl NodeCrawler {
+
+    /// Follows the node lists from `seed`.
+    /// Fails if `seed` isn't a WLED or its node list can't be fetched, problems with the other nodes end up in `Crawl::errors`.
+    pub fn crawl(&self, seed: &Url) -> Result<Crawl, WledJsonApiError> {
+        let mut seed_wled = confirm(seed, self.timeout)?
+            .ok_or_else(|| WledJsonApiError::UnexpectedResponse(format!("{seed} isn't a WLED")))?;
+        seed_wled.get_nodes_from_wled()?;
+
+        let mut visited: Vec<Url> = vec![seed_wled.url.clone()];
+        let mut found: Vec<Wled> = vec![seed_wled];
+        let mut errors: Vec<(Url, WledJsonApiError)> = Vec::new();
+        let mut frontier: Vec<usize> = vec![0];
+
+        while !frontier.is_empty() && found.len() < self.max_devices {
+            let mut next: Vec<Url> = Vec::new();
+            for n in frontier {
+                let nodes = found[n].nodes.as_ref().map(|n| n.nodes.as_slice()).unwrap_or(&[]);
+                for mut url in nodes.iter().filter_map(|node| node.url().ok()) {
+                    if let Some(port) = self.port {
+                        let _ = url.set_port(Some(port));
+                    }
+                    if !visited.contains(&url) {
+                        visited.push(url.clone());
+                        next.push(url);
+                    }
+                }
+            }
+
+            let results = confirm_each(&next, self.timeout, self.concurrency)?;
+            let mut wleds: Vec<Wled> = Vec::new();
+            for (url, result) in next.into_iter().zip(results) {
+                match result {
+                    Ok(Some(wled)) => wleds.push(wled),
+                    Ok(None) => {}
+                    Err(e) => errors.push((url, e)),
+                }
+            }
+            let urls: Vec<Url> = wleds.iter().map(|w| w.url.clone()).collect();
+            let fetched = pool(wleds.iter_mut().collect(), self.concurrency, |w| w.get_nodes_from_wled());
+            errors.extend(urls.into_iter().zip(fetched).filter_map(|(url, r)| r.err().map(|e| (url, e))));
+            let start = found.len();
+            found.extend(wleds);
+            found = dedup_by_mac(found);
+            found.truncate(self.max_devices);
+            frontier = (start.min(found.len())..found.len()).collect();
+        }
+        Ok(Crawl{ wleds: found, errors })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::{Request, TestServer};
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        // 127.0.0.1 knows .2 and .9 (nothing there), .2 knows .1 and .3, and .3 is .1 again on another interface
+        let device = |mac: &'static str, nodes: &'static str| move |r: &Request| match r.path.as_str() {
+            "/json/info" => (200, format!(r#"{{"ver":"0.14.0","brand":"WLED","mac":"{mac}"}}"#)),
+            "/json/nodes" => (200, format!(r#"{{"nodes":[{nodes}]}}"#)),
+            _ => (404, String::new()),
+        };
+        let seed = TestServer::start(device("000000000001", r#"{"name":"b","ip":"127.0.0.2"},{"name":"gone","ip":"127.0.0.9"}"#));
+        let port = seed.addr.port();
+        let _b = TestServer::start_on(&format!("127.0.0.2:{port}"), device("000000000002", r#"{"ip":"127.0.0.1"},{"ip":"127.0.0.3"}"#));
+        let _c = TestServer::start_on(&format!("127.0.0.3:{port}"), device("000000000001", ""));
+
+        let crawler = NodeCrawler{ timeout: Duration::from_millis(500), port: Some(port), ..Default::default() };
+        let crawl = crawler.crawl(&Url::parse(&format!("http://127.0.0.1:{port}/")).unwrap()).unwrap();
+        let macs: Vec<&str> = crawl.wleds.iter().map(|w| w.info.as_ref().unwrap().mac.as_deref().unwrap()).collect();
+        assert_eq!(macs, vec!["000000000001", "000000000002"]);
+        assert_eq!(crawl.wleds[1].nodes.as_ref().unwrap().nodes.len(), 2);
+        // .9 is listed but nothing answers there
+        let failed: Vec<String> = crawl.errors.iter().map(|(u, _)| u.to_string()).collect();
+        assert_eq!(failed, vec![format!("http://127.0.0.9:{port}/")]);
+    }
+
+    #[test]
+    fn seed_without_nodes_fails() {
+        let seed = TestServer::start(|r| match r.path.as_str() {
+            "/json/info" => (200, r#"{"brand":"WLED"}"#.to_string()),
+            _ => (500, String::new()),
+        });
+        assert!(NodeCrawler::default().crawl(&seed.url()).is_err());
+    }
+}
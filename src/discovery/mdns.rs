@@ -0,0 +1,218 @@
+use std::net::IpAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use reqwest::Url;
+
+use crate::discovery::{client, confirm_all, dedup_by_mac, unconfirmed, DEFAULT_CONCURRENCY, DEFAULT_CONFIRM_TIMEOUT};
+use crate::errors::WledJsonApiError;
+use crate::wled::Wled;
+
+
+/// what every WLED announces itself as
+pub const WLED_SERVICE: &str = "_wled._tcp.local.";
+/// WLED announces its web server too, along with every printer and NAS on the network
+pub const HTTP_SERVICE: &str = "_http._tcp.local.";
+
+
+/// One service instance that answered the browse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdnsDevice {
+    /// instance name, the devices mDNS name (`wled-abcdef` unless changed)
+    pub name: String,
+    /// the service type it was found under, `WLED_SERVICE` or `HTTP_SERVICE` usually
+    pub service_type: String,
+    /// like "wled-abcdef.local."
+    pub hostname: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    /// WLED puts its MAC in the TXT record of `_wled._tcp`
+    pub mac: Option<String>,
+}
+
+impl MdnsDevice {
+    fn from_service_info(service: &ServiceInfo) -> MdnsDevice {
+        let service_type = service.get_type().to_string();
+        let fullname = service.get_fullname();
+        let name = fullname.strip_suffix(&service_type)
+            .map(|n| n.trim_end_matches('.'))
+            .unwrap_or(fullname)
+            .to_string();
+        let mut addresses: Vec<IpAddr> = service.get_addresses().iter().copied().collect();
+        // IPv4 first, that's what WLED is best at
+        addresses.sort_by_key(|a| (a.is_ipv6(), *a));
+        MdnsDevice{
+            name,
+            service_type,
+            hostname: service.get_hostname().to_string(),
+            addresses,
+            port: service.get_port(),
+            mac: service.get_property_val_str("mac").map(|m| m.to_string()),
+        }
+    }
+
+    /// `http://address:port/`, on the first address (IPv4 if it has one), or on the hostname if it has none
+    pub fn url(&self) -> Result<Url, WledJsonApiError> {
+        let host = match self.addresses.first() {
+            Some(IpAddr::V4(ip)) => ip.to_string(),
+            Some(IpAddr::V6(ip)) => format!("[{ip}]"),
+            None => self.hostname.trim_end_matches('.').to_string(),
+        };
+        let url = format!("http://{host}:{}/", self.port);
+        Url::parse(&url).map_err(|_| WledJsonApiError::InvalidUrl(url))
+    }
+}
+
+
+/// Browses mDNS for WLEDs.
+///
+/// ```no_run
+/// use wled_json_api_library::discovery::mdns::MdnsDiscovery;
+///
+/// for wled in MdnsDiscovery::default().discover().unwrap() {
+///     println!("{} at {}", wled.info.as_ref().unwrap().name.as_deref().unwrap_or("?"), wled.url);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MdnsDiscovery {
+    /// browsed at the same time, `WLED_SERVICE` and `HTTP_SERVICE` by default
+    pub service_types: Vec<String>,
+    /// how long to listen for answers. WLEDs can take a second or two
+    pub browse_time: Duration,
+    /// fetch `json/info` from every candidate and drop the ones that aren't WLED.
+    /// Without it every `_http._tcp` answer ends up in the list
+    pub confirm: bool,
+    /// per candidate, when confirming
+    pub confirm_timeout: Duration,
+}
+
+impl Default for MdnsDiscovery {
+    fn default() -> Self {
+        MdnsDiscovery{
+            service_types: vec![WLED_SERVICE.to_string(), HTTP_SERVICE.to_string()],
+            browse_time: Duration::from_secs(3),
+            confirm: true,
+            confirm_timeout: DEFAULT_CONFIRM_TIMEOUT,
+        }
+    }
+}
+
+impl MdnsDiscovery {
+
+    /// only `_wled._tcp`, which nothing but WLED should answer
+    pub fn wled_only() -> MdnsDiscovery {
+        MdnsDiscovery{ service_types: vec![WLED_SERVICE.to_string()], ..Default::default() }
+    }
+
+    /// Every instance that answered within `browse_time`, once per url
+    /// (a WLED answers for both `_wled._tcp` and `_http._tcp`, the first service type wins).
+    pub fn browse(&self) -> Result<Vec<MdnsDevice>, WledJsonApiError> {
+        let daemon = ServiceDaemon::new()?;
+        let result = self.browse_with(&daemon);
+        let _ = daemon.shutdown();
+        result
+    }
+
+    /// same as `browse`, on a daemon you already have running
+    pub fn browse_with(&self, daemon: &ServiceDaemon) -> Result<Vec<MdnsDevice>, WledJsonApiError> {
+        let receivers = self.service_types.iter()
+            .map(|t| daemon.browse(t))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut found: Vec<Vec<MdnsDevice>> = vec![Vec::new(); receivers.len()];
+        let deadline = Instant::now() + self.browse_time;
+        while Instant::now() < deadline {
+            for (n, receiver) in receivers.iter().enumerate() {
+                while let Ok(event) = receiver.try_recv() {
+                    if let ServiceEvent::ServiceResolved(service) = event {
+                        let device = MdnsDevice::from_service_info(&service);
+                        // re-announcements replace what we had
+                        found[n].retain(|d| d.name != device.name);
+                        found[n].push(device);
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        for service_type in &self.service_types {
+            let _ = daemon.stop_browse(service_type);
+        }
+
+        let mut devices: Vec<MdnsDevice> = Vec::new();
+        for device in found.into_iter().flatten() {
+            let url = device.url().ok();
+            if !devices.iter().any(|d| d.url().ok() == url) {
+                devices.push(device);
+            }
+        }
+        Ok(devices)
+    }
+
+    /// `browse`, then a `Wled` for every device (confirmed ones only if `confirm`, checked in parallel
+    /// and de-duplicated by MAC).
+    /// Devices that don't answer are left out, not errors.
+    pub fn discover(&self) -> Result<Vec<Wled>, WledJsonApiError> {
+        let devices = self.browse()?;
+        self.connect(&devices)
+    }
+
+    /// the second half of `discover`, for devices from `browse_with`
+    pub fn connect(&self, devices: &[MdnsDevice]) -> Result<Vec<Wled>, WledJsonApiError> {
+        let urls: Vec<Url> = devices.iter().filter_map(|d| d.url().ok()).collect();
+        if !self.confirm {
+            let client = client(self.confirm_timeout)?;
+            return urls.iter().map(|u| unconfirmed(u, self.confirm_timeout, &client)).collect();
+        }
+        Ok(dedup_by_mac(confirm_all(&urls, self.confirm_timeout, DEFAULT_CONCURRENCY)?))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use mdns_sd::IfKind;
+    use crate::test_server::TestServer;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let wled = TestServer::start(|r| match r.path.as_str() {
+            "/json/info" => (200, r#"{"ver":"0.14.0","name":"Shelf","brand":"WLED","mac":"a842e38d9828"}"#.to_string()),
+            _ => (404, String::new()),
+        });
+        let printer = TestServer::start(|r| match r.path.as_str() {
+            "/json/info" => (200, r#"{"model":"LaserJet"}"#.to_string()),
+            _ => (404, String::new()),
+        });
+
+        // a responder in the same process, announcing one WLED and one printer
+        // (mDNS over loopback is off by default, so both ends turn it on)
+        let responder = ServiceDaemon::new().unwrap();
+        responder.enable_interface(IfKind::LoopbackV4).unwrap();
+        let txt = HashMap::from([("mac".to_string(), "a842e38d9828".to_string())]);
+        responder.register(ServiceInfo::new(WLED_SERVICE, "wled-shelf", "wled-shelf.local.", "127.0.0.1", wled.addr.port(), txt).unwrap()).unwrap();
+        responder.register(ServiceInfo::new(HTTP_SERVICE, "wled-shelf", "wled-shelf.local.", "127.0.0.1", wled.addr.port(), None).unwrap()).unwrap();
+        responder.register(ServiceInfo::new(HTTP_SERVICE, "printer", "printer.local.", "127.0.0.1", printer.addr.port(), None).unwrap()).unwrap();
+
+        let discovery = MdnsDiscovery{ browse_time: Duration::from_secs(2), ..Default::default() };
+        let browser = ServiceDaemon::new().unwrap();
+        browser.enable_interface(IfKind::LoopbackV4).unwrap();
+        let devices = discovery.browse_with(&browser).unwrap();
+        let _ = browser.shutdown();
+
+        let shelf = devices.iter().find(|d| d.name == "wled-shelf").unwrap();
+        assert_eq!(shelf.service_type, WLED_SERVICE);
+        assert_eq!(shelf.mac.as_deref(), Some("a842e38d9828"));
+        assert_eq!(shelf.url().unwrap().as_str(), format!("http://127.0.0.1:{}/", wled.addr.port()));
+        assert_eq!(devices.iter().filter(|d| d.name == "wled-shelf").count(), 1);
+        assert!(devices.iter().any(|d| d.name == "printer"));
+
+        let wleds = discovery.connect(&devices).unwrap();
+        assert_eq!(wleds.len(), 1);
+        assert_eq!(wleds[0].info.as_ref().unwrap().name.as_deref(), Some("Shelf"));
+
+        let _ = responder.shutdown();
+    }
+}
@@ -0,0 +1,141 @@
+//! Finding WLEDs on the network instead of typing in urls.
+//!
+//! `mdns` (behind the `mdns` feature) browses for the services WLED announces.
+//! Where multicast doesn't get through, `scan` probes every address of a subnet
+//! and `crawl` follows the node lists WLEDs keep of each other.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::errors::WledJsonApiError;
+use crate::wled::Wled;
+
+#[cfg(feature = "mdns")]
+pub mod mdns;
+pub mod scan;
+pub mod crawl;
+
+
+/// how long a candidate gets to answer `json/info` when confirming
+pub const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// how many candidates are probed at once
+pub const DEFAULT_CONCURRENCY: usize = 32;
+
+
+/// Fetches `json/info` from `url` and checks it's a WLED (`info.brand == "WLED"`).
+/// `Ok(None)` if something else answered, `Err` if nothing did.
+/// The returned `Wled` has `info` filled in.
+pub fn confirm(url: &Url, timeout: Duration) -> Result<Option<Wled>, WledJsonApiError> {
+    confirm_with(url, timeout, &client(timeout)?)
+}
+
+/// `confirm` on every url, `concurrency` at a time. Only the WLEDs come back, in the order of `urls`
+pub fn confirm_all(urls: &[Url], timeout: Duration, concurrency: usize) -> Result<Vec<Wled>, WledJsonApiError> {
+    Ok(confirm_each(urls, timeout, concurrency)?.into_iter().filter_map(|r| r.ok().flatten()).collect())
+}
+
+/// what `confirm` says about one url
+pub(crate) type Confirmed = Result<Option<Wled>, WledJsonApiError>;
+
+/// `confirm` on every url, `concurrency` at a time, all on one client. A result per url, in the order of `urls`
+pub(crate) fn confirm_each(urls: &[Url], timeout: Duration, concurrency: usize) -> Result<Vec<Confirmed>, WledJsonApiError> {
+    let client = client(timeout)?;
+    Ok(pool(urls.iter().collect(), concurrency, |url| confirm_with(url, timeout, &client)))
+}
+
+/// `f` on every item, `concurrency` at a time. A result per item, in the order of `items`.
+/// If `f` panics that item gets an `UnexpectedResponse`
+pub(crate) fn pool<T: Send, R: Send>(items: Vec<T>, concurrency: usize, f: impl Fn(T) -> Result<R, WledJsonApiError> + Sync) -> Vec<Result<R, WledJsonApiError>> {
+    let workers = concurrency.clamp(1, items.len().max(1));
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let results: Mutex<Vec<(usize, Result<R, WledJsonApiError>)>> = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                loop {
+                    let Some((n, item)) = queue.lock().unwrap().next() else { break };
+                    let result = catch_unwind(AssertUnwindSafe(|| f(item))).unwrap_or_else(|_| {
+                        Err(WledJsonApiError::UnexpectedResponse("the thread talking to this device panicked".to_string()))
+                    });
+                    results.lock().unwrap().push((n, result));
+                }
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(n, _)| *n);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Drops every `Wled` whose `info.mac` was already seen (the same device on wifi and ethernet,
+/// or found twice). Ones without `info` or a MAC are only dropped if their url was seen.
+pub fn dedup_by_mac(wleds: Vec<Wled>) -> Vec<Wled> {
+    let mut kept: Vec<Wled> = Vec::with_capacity(wleds.len());
+    for wled in wleds {
+        let mac = wled.info.as_ref().and_then(|i| i.mac.as_deref()).map(|m| m.to_ascii_lowercase());
+        let seen = kept.iter().any(|k| {
+            let kept_mac = k.info.as_ref().and_then(|i| i.mac.as_deref()).map(|m| m.to_ascii_lowercase());
+            match (&mac, kept_mac) {
+                (Some(a), Some(b)) => *a == b,
+                _ => k.url == wled.url,
+            }
+        });
+        if !seen {
+            kept.push(wled);
+        }
+    }
+    kept
+}
+
+/// one client for all the candidates of a search, instead of a connection pool each
+pub(crate) fn client(timeout: Duration) -> Result<reqwest::blocking::Client, WledJsonApiError> {
+    Ok(reqwest::blocking::ClientBuilder::new().connect_timeout(timeout).build()?)
+}
+
+/// a handle for `url` on `client` without talking to it
+pub(crate) fn unconfirmed(url: &Url, timeout: Duration, client: &reqwest::blocking::Client) -> Result<Wled, WledJsonApiError> {
+    Wled::builder(url)
+        .fetch_timeout(timeout)
+        .probe(false)
+        .client(client.clone())
+        .build()
+}
+
+fn confirm_with(url: &Url, timeout: Duration, client: &reqwest::blocking::Client) -> Result<Option<Wled>, WledJsonApiError> {
+    let mut wled = unconfirmed(url, timeout, client)?;
+    wled.get_info_from_wled()?;
+    let is_wled = wled.info.as_ref()
+        .and_then(|i| i.brand.as_deref())
+        .is_some_and(|b| b == "WLED");
+    Ok(if is_wled { Some(wled) } else { None })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    #[test]
+    fn pool_is_bounded() {
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let results = pool((0..10).collect(), 3, |n: usize| {
+            most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+            if n == 4 {
+                panic!("device 4 is cursed");
+            }
+            Ok(n * 2)
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+        assert_eq!(results.len(), 10);
+        assert!(matches!(results[4], Err(WledJsonApiError::UnexpectedResponse(_))));
+        assert_eq!(results[9].as_ref().unwrap(), &18);
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::discovery::{confirm_all, dedup_by_mac, DEFAULT_CONCURRENCY};
+use crate::errors::WledJsonApiError;
+use crate::wled::Wled;
+
+
+/// An IPv4 range like `192.168.1.0/24`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub address: Ipv4Addr,
+    /// 0 to 32
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    pub fn new(address: Ipv4Addr, prefix: u8) -> Result<Ipv4Cidr, WledJsonApiError> {
+        if prefix > 32 {
+            return Err(WledJsonApiError::InvalidValue(format!("/{prefix} isn't a prefix length")));
+        }
+        Ok(Ipv4Cidr{ address, prefix })
+    }
+
+    fn mask(&self) -> u32 {
+        u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0)
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) & self.mask())
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) | !self.mask())
+    }
+
+    /// every address a device can have, so without the network and broadcast address (except on /31 and /32)
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let (first, last) = (u32::from(self.network()), u32::from(self.broadcast()));
+        let (first, last) = if self.prefix >= 31 { (first, last) } else { (first + 1, last - 1) };
+        (first..=last).map(Ipv4Addr::from)
+    }
+
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & self.mask() == u32::from(self.network())
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = WledJsonApiError;
+
+    /// `a.b.c.d/n`, or a lone address as a /32
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WledJsonApiError::InvalidValue(format!("{s} isn't an IPv4 range"));
+        let (address, prefix) = s.trim().split_once('/').unwrap_or((s.trim(), "32"));
+        Ipv4Cidr::new(address.parse().map_err(|_| invalid())?, prefix.parse().map_err(|_| invalid())?)
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+
+/// Probes `json/info` on every address of a subnet, for networks where mDNS doesn't get through.
+///
+/// ```no_run
+/// use wled_json_api_library::discovery::scan::SubnetScan;
+///
+/// let wleds = SubnetScan::new("192.168.1.0/24".parse().unwrap()).scan().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct SubnetScan {
+    pub cidr: Ipv4Cidr,
+    /// 80 on every real WLED
+    pub port: u16,
+    /// per address. Nothing answering is the common case, so keep it short
+    pub timeout: Duration,
+    /// addresses probed at once
+    pub concurrency: usize,
+}
+
+impl SubnetScan {
+    /// 300 ms per address, 32 at a time: a /24 takes about 3 seconds
+    pub fn new(cidr: Ipv4Cidr) -> SubnetScan {
+        SubnetScan{
+            cidr,
+            port: 80,
+            timeout: Duration::from_millis(300),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Every WLED in the range, with `info` fetched, de-duplicated by MAC.
+    /// Ranges bigger than a /16 are an `InvalidValue`, that many probes is never what you want.
+    pub fn scan(&self) -> Result<Vec<Wled>, WledJsonApiError> {
+        if self.cidr.prefix < 16 {
+            return Err(WledJsonApiError::InvalidValue(format!("{} is too big to scan", self.cidr)));
+        }
+        let urls = self.cidr.hosts()
+            .map(|ip| Url::parse(&format!("http://{ip}:{}/", self.port)).map_err(|_| WledJsonApiError::InvalidUrl(ip.to_string())))
+            .collect::<Result<Vec<Url>, _>>()?;
+        Ok(dedup_by_mac(confirm_all(&urls, self.timeout, self.concurrency)?))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_server::TestServer;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let cidr: Ipv4Cidr = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(cidr.network(), Ipv4Addr::new(192, 168, 1, 0));
+        assert_eq!(cidr.broadcast(), Ipv4Addr::new(192, 168, 1, 255));
+        assert_eq!(cidr.hosts().count(), 254);
+        assert!(cidr.contains(Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(!cidr.contains(Ipv4Addr::new(192, 168, 2, 1)));
+        assert_eq!("10.0.0.5".parse::<Ipv4Cidr>().unwrap().hosts().collect::<Vec<_>>(), vec![Ipv4Addr::new(10, 0, 0, 5)]);
+        assert!("10.0.0.0/33".parse::<Ipv4Cidr>().is_err());
+        assert!(SubnetScan::new("10.0.0.0/8".parse().unwrap()).scan().is_err());
+
+        // a WLED on 127.0.0.1 and 127.0.0.2, something else on 127.0.0.5, nothing on the rest
+        let info = |mac: &'static str| move |r: &crate::test_server::Request| match r.path.as_str() {
+            "/json/info" => (200, format!(r#"{{"ver":"0.14.0","brand":"WLED","mac":"{mac}"}}"#)),
+            _ => (404, String::new()),
+        };
+        let first = TestServer::start(info("a842e38d9828"));
+        let port = first.addr.port();
+        let _second = TestServer::start_on(&format!("127.0.0.2:{port}"), info("a842e38d0000"));
+        let _other = TestServer::start_on(&format!("127.0.0.5:{port}"), |_| (200, r#"{"hello":"there"}"#.to_string()));
+
+        let mut scan = SubnetScan::new("127.0.0.0/29".parse().unwrap());
+        scan.port = port;
+        let wleds = scan.scan().unwrap();
+        let macs: Vec<&str> = wleds.iter().map(|w| w.info.as_ref().unwrap().mac.as_deref().unwrap()).collect();
+        assert_eq!(macs, vec!["a842e38d9828", "a842e38d0000"]);
+    }
+}
@@ -1,4 +1,6 @@
+use crate::structures::cfg::cfg_if2::DmxMode;
 use thiserror::Error;
+use crate::structures::response::WledErrorCode;
 
 #[derive(Error, Debug)]
 pub enum WledJsonApiError {
@@ -15,4 +17,36 @@ pub enum WledJsonApiError {
     #[error("Attempted to read a key that doesn't exist \
             (either you need to read it from the server, or the server didn't send one)")]
     MissingKey,
+    #[error("WLED answered with error code {0:?}")]
+    WledError(WledErrorCode),
+    #[error("WLED answered with something this library doesn't understand: {0}")]
+    UnexpectedResponse(String),
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("Can't make sense of url {0}")]
+    InvalidUrl(String),
+    #[error("websocket error: {0}")]
+    WebSocketError(Box<tungstenite::Error>),
+    #[error("websocket is closed")]
+    WebSocketClosed,
+    #[error("invalid playlist: {0}")]
+    InvalidPlaylist(String),
+    #[error("invalid color: {0}")]
+    InvalidColor(String),
+    #[error("invalid value: {0}")]
+    InvalidValue(String),
+    #[error("can't stream frames in DMX mode {0:?}")]
+    UnsupportedDmxMode(DmxMode),
+    #[cfg(feature = "mdns")]
+    #[error("mDNS error: {0}")]
+    MdnsError(#[from] mdns_sd::Error),
+}
+
+impl From<tungstenite::Error> for WledJsonApiError {
+    fn from(e: tungstenite::Error) -> Self {
+        match e {
+            tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => WledJsonApiError::WebSocketClosed,
+            e => WledJsonApiError::WebSocketError(Box::new(e)),
+        }
+    }
 }
@@ -0,0 +1,693 @@
+//! Many WLEDs at once. Every operation runs on all the selected devices in parallel,
+//! each with its own timeouts, and hands back one result per device instead of stopping at the first error.
+//!
+//! ```no_run
+//! use reqwest::Url;
+//! use wled_json_api_library::fleet::{Fleet, Select};
+//! use wled_json_api_library::structures::state::State;
+//! use wled_json_api_library::wled::Wled;
+//!
+//! let mut fleet = Fleet::new();
+//! let wled = Wled::try_from_url(&Url::parse("http://192.168.1.40/").unwrap()).unwrap();
+//! fleet.add("porch", wled, &["outside"]);
+//!
+//! let results = fleet.apply_state(&Select::tag("outside"), &State{ on: Some(false), ..Default::default() });
+//! for (id, result) in &results {
+//!     if let Err(e) = result {
+//!         println!("{id} didn't turn off: {e}");
+//!     }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::builder::Timeouts;
+use crate::errors::WledJsonApiError;
+use crate::notifier::{Notifier, NotifierPacket, NOTIFIER_PORT};
+use crate::structures::response::FlushResponse;
+use crate::structures::state::State;
+use crate::wled::Wled;
+
+
+/// one entry per selected device, by id
+pub type FleetResults<T> = BTreeMap<String, Result<T, WledJsonApiError>>;
+
+
+/// A device in a `Fleet`
+#[derive(Debug)]
+pub struct FleetDevice {
+    pub wled: Wled,
+    pub tags: BTreeSet<String>,
+}
+
+
+/// Which devices of a fleet an operation runs on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Select {
+    All,
+    /// every device with this tag
+    Tag(String),
+    /// every device with at least one of these tags
+    AnyTag(Vec<String>),
+    /// these devices, by id. Ids that aren't in the fleet are skipped
+    Ids(Vec<String>),
+}
+
+impl Select {
+    pub fn tag(tag: &str) -> Select {
+        Select::Tag(tag.to_string())
+    }
+
+    pub fn matches(&self, id: &str, device: &FleetDevice) -> bool {
+        match self {
+            Select::All => true,
+            Select::Tag(t) => device.tags.contains(t),
+            Select::AnyTag(tags) => tags.iter().any(|t| device.tags.contains(t)),
+            Select::Ids(ids) => ids.iter().any(|i| i == id),
+        }
+    }
+}
+
+
+/// how many devices a `Fleet` talks to at once
+pub const DEFAULT_CONCURRENCY: usize = 32;
+
+
+/// A bunch of `Wled`s, each with an id and any number of tags
+#[derive(Debug)]
+pub struct Fleet {
+    pub devices: BTreeMap<String, FleetDevice>,
+    /// devices talked to at once (a thread each), the rest wait for a free one. `DEFAULT_CONCURRENCY` by default
+    pub concurrency: usize,
+}
+
+impl Default for Fleet {
+    fn default() -> Self {
+        Fleet{
+            devices: BTreeMap::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+}
+
+impl Fleet {
+
+    pub fn new() -> Fleet {
+        Fleet::default()
+    }
+
+    /// adds (or replaces) the device `id`
+    pub fn add(&mut self, id: &str, wled: Wled, tags: &[&str]) {
+        let tags = tags.iter().map(|t| t.to_string()).collect();
+        self.devices.insert(id.to_string(), FleetDevice{ wled, tags });
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<FleetDevice> {
+        self.devices.remove(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Wled> {
+        self.devices.get(id).map(|d| &d.wled)
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Wled> {
+        self.devices.get_mut(id).map(|d| &mut d.wled)
+    }
+
+    /// false if there's no device `id`
+    pub fn tag(&mut self, id: &str, tag: &str) -> bool {
+        self.devices.get_mut(id).map(|d| d.tags.insert(tag.to_string())).is_some()
+    }
+
+    /// false if there's no device `id`
+    pub fn untag(&mut self, id: &str, tag: &str) -> bool {
+        self.devices.get_mut(id).map(|d| d.tags.remove(tag)).is_some()
+    }
+
+    /// ids of the devices `select` picks
+    pub fn ids(&self, select: &Select) -> Vec<String> {
+        self.devices.iter()
+            .filter(|(id, d)| select.matches(id, d))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Sets the fetch, flush and probe timeouts of the selected devices.
+    /// The connect timeout is part of each devices HTTP client, so only a new `Wled` changes that
+    pub fn set_timeouts(&mut self, select: &Select, timeouts: &Timeouts) {
+        for (id, device) in self.devices.iter_mut() {
+            if select.matches(id, device) {
+                device.wled.timeouts = Timeouts{ connect: device.wled.timeouts.connect, ..timeouts.clone() };
+            }
+        }
+    }
+
+    /// Runs `f` on every selected device, `concurrency` of them at a time.
+    /// Everything else in here is built on this.
+    pub fn for_each<T, F>(&mut self, select: &Select, f: F) -> FleetResults<T>
+    where T: Send, F: Fn(&mut Wled) -> Result<T, WledJsonApiError> + Sync {
+        let selected: Vec<(String, &mut Wled)> = self.devices.iter_mut()
+            .filter(|(id, d)| select.matches(id, d))
+            .map(|(id, d)| (id.clone(), &mut d.wled))
+            .collect();
+        let workers = self.concurrency.clamp(1, selected.len().max(1));
+        let queue = Mutex::new(selected.into_iter());
+        let results = Mutex::new(BTreeMap::new());
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| {
+                    loop {
+                        let Some((id, wled)) = queue.lock().unwrap().next() else { break };
+                        let result = catch_unwind(AssertUnwindSafe(|| f(wled))).unwrap_or_else(|_| {
+                            Err(WledJsonApiError::UnexpectedResponse("the thread talking to this device panicked".to_string()))
+                        });
+                        results.lock().unwrap().insert(id, result);
+                    }
+                });
+            }
+        });
+        results.into_inner().unwrap()
+    }
+
+    /// `get_state_from_wled` on every selected device
+    pub fn get_state(&mut self, select: &Select) -> FleetResults<()> {
+        self.for_each(select, |w| w.get_state_from_wled())
+    }
+
+    /// `get_info_from_wled` on every selected device
+    pub fn get_info(&mut self, select: &Select) -> FleetResults<()> {
+        self.for_each(select, |w| w.get_info_from_wled())
+    }
+
+    /// `get_state_info_from_wled` on every selected device
+    pub fn get_state_info(&mut self, select: &Select) -> FleetResults<()> {
+        self.for_each(select, |w| w.get_state_info_from_wled())
+    }
+
+    /// `get_cfg_from_wled` on every selected device
+    pub fn get_cfg(&mut self, select: &Select) -> FleetResults<()> {
+        self.for_each(select, |w| w.get_cfg_from_wled())
+    }
+
+    /// `flush_state_verbose` on every selected device, each sending its own `state`
+    pub fn flush_state(&mut self, select: &Select) -> FleetResults<FlushResponse> {
+        self.for_each(select, |w| w.flush_state_verbose())
+    }
+
+    /// Sends the same `patch` to every selected device. Their `state` is replaced by what each one answers,
+    /// and left alone if it fails (the patch isn't kept around).
+    pub fn apply_state(&mut self, select: &Select, patch: &State) -> FleetResults<FlushResponse> {
+        // serialized per device, so if it fails each one gets its own error
+        self.for_each(select, |w| {
+            let response = w.post_state(verbose_packet(patch)?)?;
+            if let FlushResponse::State(s) = &response {
+                w.state = Some(s.clone());
+            }
+            Ok(response)
+        })
+    }
+
+    /// Sends the same `patch` to every selected device so they all get it at (nearly) the same moment,
+    /// for transitions (`tt`) that should start together.
+    ///
+    /// Every device gets its own connection, opened beforehand, and the whole request minus its last byte.
+    /// Once every device is staged (or `stage_timeout` is up) they're all released together by sending that last byte.
+    /// These requests go over plain sockets rather than the devices HTTP client, so headers from the builder
+    /// aren't sent and https isn't supported.
+    ///
+    /// Devices that couldn't be staged in time get their error and are left out of the release.
+    /// `state` is replaced by what each device answers, like `apply_state`.
+    /// Unlike everything else here this ignores `concurrency`, every device has to be staged at the same time.
+    pub fn sync_apply(&mut self, select: &Select, patch: &State, options: &SyncOptions) -> SyncReport {
+        let count = self.ids(select).len();
+        // every device thread, and this one for the notifier
+        let gate = Gate::new(count + 1, Instant::now() + options.stage_timeout);
+        let notifications: Mutex<Vec<(SocketAddr, NotifierPacket)>> = Mutex::new(Vec::new());
+        let ports: Vec<u16> = options.notifier.map_or_else(Vec::new, notifier_ports);
+
+        let (results, notifier): (FleetResults<(Instant, Duration, FlushResponse)>, _) = thread::scope(|s| {
+            let (gate, notifications, ports) = (&gate, &notifications, &ports);
+            let handles: Vec<_> = self.devices.iter_mut()
+                .filter(|(id, d)| select.matches(id, d))
+                .map(|(id, d)| (id.clone(), s.spawn(move || {
+                    let staged = catch_unwind(AssertUnwindSafe(|| {
+                        let notification = match options.notifier {
+                            Some(_) => {
+                                d.wled.get_state_from_wled()?;
+                                Some(notification(d.wled.state.as_ref().ok_or(WledJsonApiError::MissingKey)?, patch)?)
+                            }
+                            None => None,
+                        };
+                        let staged = stage(&d.wled, &verbose_packet(patch)?, gate.deadline)?;
+                        Ok((staged, notification))
+                    })).unwrap_or_else(|_| Err(WledJsonApiError::UnexpectedResponse("the thread talking to this device panicked".to_string())));
+
+                    // whatever happened this has to be reached, or everyone waits for the deadline
+                    let in_time = gate.wait(|| {
+                        if let Ok(((stream, _), Some(packet))) = &staged {
+                            if let Ok(peer) = stream.peer_addr() {
+                                let mut notifications = notifications.lock().unwrap();
+                                notifications.extend(ports.iter().map(|p| (SocketAddr::new(peer.ip(), *p), packet.clone())));
+                            }
+                        }
+                    });
+                    let ((mut stream, last), _) = staged?;
+                    if !in_time {
+                        return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "wasn't staged within stage_timeout").into());
+                    }
+                    stream.write_all(&[last])?;
+                    let fired = Instant::now();
+                    stream.set_read_timeout(Some(options.answer_timeout))?;
+                    let response = read_response(&mut stream)?;
+                    let answered = fired.elapsed();
+                    if let FlushResponse::State(s) = &response {
+                        d.wled.state = Some(s.clone());
+                    }
+                    Ok((fired, answered, response))
+                })))
+                .collect();
+
+            gate.wait(|| ());
+            let notifier = options.notifier.map(|n| n.send_each(&notifications.lock().unwrap()));
+
+            let results = handles.into_iter()
+                .map(|(id, h)| {
+                    let result = h.join().unwrap_or_else(|_| {
+                        Err(WledJsonApiError::UnexpectedResponse("the thread talking to this device panicked".to_string()))
+                    });
+                    (id, result)
+                })
+                .collect();
+            (results, notifier)
+        });
+
+        let first = results.values().filter_map(|r| r.as_ref().ok()).map(|(fired, _, _)| *fired).min();
+        let devices = results.into_iter()
+            .map(|(id, r)| (id, r.map(|(fired, answered, response)| SyncTiming{
+                skew: first.map_or(Duration::ZERO, |f| fired - f),
+                answered,
+                response,
+            })))
+            .collect();
+        SyncReport{ devices, notifier }
+    }
+}
+
+
+/// How `Fleet::sync_apply` stages and releases
+#[derive(Debug, Clone)]
+pub struct SyncOptions<'a> {
+    /// For every device to be staged: connected, everything but the last byte sent, and with a notifier its state fetched.
+    /// Devices that aren't staged by then are left out, the rest are released without them
+    pub stage_timeout: Duration,
+    /// for each device to answer, after the release
+    pub answer_timeout: Duration,
+    /// Also send every staged device a notification right at the release, for devices that have "Receive" on.
+    /// Each one gets its own state (fetched while staging) with the patch on top, so whatever the patch leaves out
+    /// (colors, effect, ...) stays how it was. They're sent straight to each device, on the ports of the notifiers
+    /// targets (`NOTIFIER_PORT` if it has none).
+    /// Relative values, random values and presets can't be worked out here, with a notifier they're an `InvalidValue`
+    pub notifier: Option<&'a Notifier>,
+}
+
+impl Default for SyncOptions<'_> {
+    fn default() -> Self {
+        SyncOptions{
+            stage_timeout: Duration::from_millis(2000),
+            answer_timeout: Duration::from_millis(2000),
+            notifier: None,
+        }
+    }
+}
+
+
+/// What `Fleet::sync_apply` did
+#[derive(Debug)]
+pub struct SyncReport {
+    /// how each selected device did
+    pub devices: FleetResults<SyncTiming>,
+    /// if the notifications went out, None without a notifier
+    pub notifier: Option<Result<(), WledJsonApiError>>,
+}
+
+
+/// How one device did in `Fleet::sync_apply`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncTiming {
+    /// How long after the first device the last byte of this ones request was written, measured here.
+    /// That's only the local side, network and device latency come on top and aren't in here
+    pub skew: Duration,
+    /// from its release to its answer
+    pub answered: Duration,
+    pub response: FlushResponse,
+}
+
+/// the biggest `skew` of the devices that made it, zero if none did
+pub fn max_skew(results: &FleetResults<SyncTiming>) -> Duration {
+    results.values().filter_map(|r| r.as_ref().ok()).map(|t| t.skew).max().unwrap_or_default()
+}
+
+
+/// A `Barrier` that gives up at `deadline`, so a device that never gets staged can't hold up the rest forever
+struct Gate {
+    waiting: Mutex<usize>,
+    released: Condvar,
+    deadline: Instant,
+}
+
+impl Gate {
+    fn new(count: usize, deadline: Instant) -> Gate {
+        Gate{ waiting: Mutex::new(count), released: Condvar::new(), deadline }
+    }
+
+    /// Waits for everyone else, or the deadline. False (without waiting or running `on_time`) if that already passed.
+    /// `on_time` runs while everyone else is still held, so whatever it does is done before the release
+    fn wait(&self, on_time: impl FnOnce()) -> bool {
+        let mut waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+        if Instant::now() >= self.deadline {
+            return false;
+        }
+        on_time();
+        *waiting -= 1;
+        if *waiting == 0 {
+            self.released.notify_all();
+            return true;
+        }
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        drop(self.released.wait_timeout_while(waiting, timeout, |w| *w > 0).unwrap_or_else(|e| e.into_inner()));
+        true
+    }
+}
+
+
+/// the ports the notifier sends to, one each
+fn notifier_ports(notifier: &Notifier) -> Vec<u16> {
+    let mut ports: Vec<u16> = notifier.targets.iter().map(|t| t.port()).collect();
+    ports.sort();
+    ports.dedup();
+    if ports.is_empty() {
+        ports.push(NOTIFIER_PORT);
+    }
+    ports
+}
+
+/// the notification for a device in `state` that gets `patch`
+fn notification(state: &State, patch: &State) -> Result<NotifierPacket, WledJsonApiError> {
+    if patch.ps.is_some() || patch.pl.is_some() {
+        return Err(WledJsonApiError::InvalidValue("a notification can't know what a preset or playlist looks like".to_string()));
+    }
+    let mut merged = serde_json::to_value(state)?;
+    merge_json(&mut merged, serde_json::to_value(patch)?)?;
+    let mut packet = NotifierPacket::from_state(&serde_json::from_value(merged)?);
+    if let Some(tt) = patch.tt {
+        packet.transition = tt as u16 * 100;
+    }
+    Ok(packet)
+}
+
+/// `patch` on top of `base` the way WLED applies it: segments by `id` (or position without one),
+/// colors slot by slot (an empty slot is left alone), everything else replaced.
+fn merge_json(base: &mut Value, patch: Value) -> Result<(), WledJsonApiError> {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                match base.get_mut(&key) {
+                    Some(Value::Array(b)) if key == "seg" => {
+                        let Value::Array(segs) = value else { continue };
+                        for (n, seg) in segs.into_iter().enumerate() {
+                            let at = seg.get("id").and_then(|id| b.iter().position(|s| s.get("id") == Some(id))).unwrap_or(n);
+                            match b.get_mut(at) {
+                                Some(s) => merge_json(s, seg)?,
+                                None => b.push(seg),
+                            }
+                        }
+                    }
+                    Some(Value::Array(b)) if key == "col" => {
+                        let Value::Array(slots) = value else { continue };
+                        for (n, slot) in slots.into_iter().enumerate() {
+                            if slot.as_array().is_some_and(|s| s.is_empty()) {
+                                continue;
+                            }
+                            match b.get_mut(n) {
+                                Some(c) => *c = slot,
+                                None => b.push(slot),
+                            }
+                        }
+                    }
+                    // "~", "~-", "r" and the like, only the device knows what they come out as
+                    Some(Value::Number(_)) if value.is_string() => {
+                        return Err(WledJsonApiError::InvalidValue(format!("{key} = {value} can't go in a notification")));
+                    }
+                    Some(b) => merge_json(b, value)?,
+                    None => { base.insert(key, value); }
+                }
+            }
+        }
+        (base, patch) => *base = patch,
+    }
+    Ok(())
+}
+
+/// `patch` as JSON with `v` set, so devices answer with their full state
+fn verbose_packet(patch: &State) -> Result<String, WledJsonApiError> {
+    let mut verbose = patch.clone();
+    verbose.v = Some(true);
+    (&verbose).try_into()
+}
+
+/// connects to `wled` and sends a POST of `body` to `json/state`, all but the last byte (which is returned)
+fn stage(wled: &Wled, body: &str, deadline: Instant) -> Result<(TcpStream, u8), WledJsonApiError> {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    if timeout.is_zero() {
+        return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "wasn't staged within stage_timeout").into());
+    }
+    let url = wled.endpoint("json/state")?;
+    if url.scheme() != "http" {
+        return Err(WledJsonApiError::InvalidUrl(url.to_string()));
+    }
+    let host = url.host_str().ok_or_else(|| WledJsonApiError::InvalidUrl(url.to_string()))?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addr: SocketAddr = (host.trim_start_matches('[').trim_end_matches(']'), port).to_socket_addrs()?
+        .next()
+        .ok_or_else(|| WledJsonApiError::InvalidUrl(url.to_string()))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(timeout))?;
+    let host_header = match url.port() {
+        Some(p) => format!("{host}:{p}"),
+        None => host.to_string(),
+    };
+    let path = match url.query() {
+        Some(q) => format!("{}?{q}", url.path()),
+        None => url.path().to_string(),
+    };
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {host_header}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+    let (last, rest) = request.as_bytes().split_last().ok_or(WledJsonApiError::FlushNone)?;
+    stream.write_all(rest)?;
+    stream.flush()?;
+    Ok((stream, *last))
+}
+
+/// reads a whole `Connection: close` answer and parses it like `flush_state_verbose` does
+fn read_response(stream: &mut TcpStream) -> Result<FlushResponse, WledJsonApiError> {
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    let split = find(&raw, b"\r\n\r\n")
+        .ok_or_else(|| WledJsonApiError::UnexpectedResponse(String::from_utf8_lossy(&raw).to_string()))?;
+    let head = String::from_utf8_lossy(&raw[..split]);
+    let body = &raw[split + 4..];
+    let status = head.split_whitespace().nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .and_then(|s| reqwest::StatusCode::from_u16(s).ok())
+        .ok_or_else(|| WledJsonApiError::UnexpectedResponse(head.to_string()))?;
+    let chunked = head.lines().any(|l| {
+        l.split_once(':').is_some_and(|(k, v)| k.trim().eq_ignore_ascii_case("transfer-encoding") && v.trim().eq_ignore_ascii_case("chunked"))
+    });
+    let body = if chunked { dechunk(body)? } else { body.to_vec() };
+    let body = String::from_utf8_lossy(&body);
+    FlushResponse::from_http(status, &body)
+}
+
+/// the body of a `Transfer-Encoding: chunked` answer put back together, an error if it stops before the last (empty) chunk
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, WledJsonApiError> {
+    let truncated = || WledJsonApiError::UnexpectedResponse("chunked answer ends in the middle of a chunk".to_string());
+    let mut out = Vec::new();
+    loop {
+        let line = find(body, b"\r\n").ok_or_else(truncated)?;
+        let size = String::from_utf8_lossy(&body[..line]);
+        let size = usize::from_str_radix(size.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| WledJsonApiError::UnexpectedResponse(format!("bad chunk size {size:?}")))?;
+        let rest = &body[line + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        let chunk = rest.get(..size).ok_or_else(truncated)?;
+        out.extend_from_slice(chunk);
+        body = rest[size..].strip_prefix(b"\r\n").ok_or_else(truncated)?;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::test_server::TestServer;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let ok = || TestServer::start(|r| match (r.method.as_str(), r.path.as_str()) {
+            ("GET", "/json/state") => (200, r#"{"on":true,"bri":10}"#.to_string()),
+            ("POST", "/json/state") => (200, r#"{"on":false,"bri":10}"#.to_string()),
+            _ => (404, String::new()),
+        });
+        let (porch, garage) = (ok(), ok());
+        let broken = TestServer::start(|_| (500, "nope".to_string()));
+
+        let build = |s: &TestServer| Wled::builder(&s.url()).probe(false).fetch_timeout(Duration::from_secs(2)).build().unwrap();
+        let mut fleet = Fleet::new();
+        fleet.add("porch", build(&porch), &["outside"]);
+        fleet.add("garage", build(&garage), &["outside", "garage"]);
+        fleet.add("broken", build(&broken), &["outside"]);
+        fleet.add("desk", build(&ok()), &[]);
+        assert_eq!(fleet.ids(&Select::tag("outside")), vec!["broken", "garage", "porch"]);
+        assert!(fleet.tag("desk", "inside"));
+        assert!(!fleet.tag("nope", "inside"));
+
+        let results = fleet.get_state(&Select::All);
+        assert_eq!(results.len(), 4);
+        assert!(results["broken"].is_err());
+        assert_eq!(fleet.get("porch").unwrap().state.as_ref().unwrap().bri, Some(10));
+
+        let results = fleet.apply_state(&Select::tag("outside"), &State{ on: Some(false), ..Default::default() });
+        assert_eq!(results.len(), 3);
+        assert!(results["porch"].is_ok() && results["garage"].is_ok());
+        assert!(results["broken"].is_err());
+        assert_eq!(fleet.get("garage").unwrap().state.as_ref().unwrap().on, Some(false));
+        assert_eq!(fleet.get("desk").unwrap().state.as_ref().unwrap().on, Some(true));
+        let posted = porch.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(posted.body, r#"{"on":false,"v":true}"#);
+    }
+
+    #[test]
+    fn for_each_caps_workers() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let mut fleet = Fleet{ concurrency: 2, ..Fleet::new() };
+        for port in 1..=5 {
+            let url = reqwest::Url::parse(&format!("http://127.0.0.1:{port}/")).unwrap();
+            fleet.add(&port.to_string(), Wled::builder(&url).probe(false).build().unwrap(), &[]);
+        }
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let results = fleet.for_each(&Select::All, |w| {
+            most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            if w.url.port() == Some(3) {
+                panic!("device 3 is cursed");
+            }
+            Ok(())
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(results.len(), 5);
+        assert!(results["3"].is_err());
+        assert!(results.iter().filter(|(id, _)| *id != "3").all(|(_, r)| r.is_ok()));
+    }
+
+    #[test]
+    fn sync_apply() {
+        let device = |fx: u8, col: &'static str| move |r: &crate::test_server::Request| match (r.method.as_str(), r.path.as_str()) {
+            ("GET", "/json/state") => (200, format!(r#"{{"on":true,"bri":40,"transition":7,"seg":[{{"id":0,"start":0,"stop":30,"fx":{fx},"sx":99,"col":[{col},[0,0,0],[0,0,0]]}}]}}"#)),
+            ("POST", "/json/state") => (200, r#"{"on":true,"bri":200,"transition":7}"#.to_string()),
+            _ => (404, String::new()),
+        };
+        let (a, b) = (TestServer::start(device(5, "[255,0,0]")), TestServer::start(device(9, "[0,0,255]")));
+        // nothing listens here anymore
+        let gone = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let build = |url: &reqwest::Url| Wled::builder(url).probe(false).build().unwrap();
+        let mut fleet = Fleet::new();
+        fleet.add("a", build(&a.url()), &["stage"]);
+        fleet.add("b", build(&b.url()), &["stage"]);
+        fleet.add("gone", build(&reqwest::Url::parse(&format!("http://{gone}/")).unwrap()), &["stage"]);
+
+        let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rx_addr = rx.local_addr().unwrap();
+        let receiver = Notifier::new(vec![rx], vec![]).unwrap();
+        let notifier = Notifier::new(vec![std::net::UdpSocket::bind("127.0.0.1:0").unwrap()], vec![rx_addr]).unwrap();
+
+        // only brightness and the transition, the colors and effects have to stay what they were
+        let patch = State{ bri: Some(200), tt: Some(20), ..Default::default() };
+        let options = SyncOptions{ notifier: Some(&notifier), ..Default::default() };
+        let report = fleet.sync_apply(&Select::tag("stage"), &patch, &options);
+
+        assert!(matches!(report.notifier, Some(Ok(()))));
+        let results = &report.devices;
+        assert_eq!(results.len(), 3);
+        assert!(results["gone"].is_err());
+        let (ta, tb) = (results["a"].as_ref().unwrap(), results["b"].as_ref().unwrap());
+        assert!(ta.skew.is_zero() || tb.skew.is_zero());
+        assert!(max_skew(results) < Duration::from_millis(500));
+        assert_eq!(fleet.get("a").unwrap().state.as_ref().unwrap().bri, Some(200));
+        let posted = a.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(posted.body, r#"{"bri":200,"tt":20,"v":true}"#);
+
+        // both devices are on 127.0.0.1, so both of their notifications end up here
+        let mut packets: Vec<NotifierPacket> = (0..2).map(|_| receiver.recv(Some(Duration::from_secs(5))).unwrap().1).collect();
+        packets.sort_by_key(|p| p.effect);
+        for (packet, (fx, rgb)) in packets.iter().zip([(5, (255, 0, 0)), (9, (0, 0, 255))]) {
+            assert_eq!((packet.bri, packet.transition), (200, 2000));
+            assert_eq!((packet.effect, packet.speed), (fx, 99));
+            assert_eq!((packet.colors[0].r, packet.colors[0].g, packet.colors[0].b), rgb);
+            assert_eq!(packet.segments[0].stop, 30);
+        }
+
+        // a relative effect can't be put in a notification
+        let patch = State{ bri: Some(10), seg: Some(vec![crate::structures::state::Seg{ fx: Some(crate::structures::relative::RelativeValue::Increment(None)), ..Default::default() }]), ..Default::default() };
+        let report = fleet.sync_apply(&Select::Ids(vec!["a".to_string()]), &patch, &options);
+        assert!(matches!(report.devices["a"], Err(WledJsonApiError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn dechunk_bytes() {
+        // "é" is two bytes, split over two chunks
+        let body = b"7\r\n{\"n\":\"\xC3\r\n4\r\n\xA9\"}\n\r\n0\r\n\r\n";
+        let out = dechunk(body).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"n\":\"\u{e9}\"}\n");
+        assert!(matches!(dechunk(b"5\r\n{\"n\""), Err(WledJsonApiError::UnexpectedResponse(_))));
+        assert!(matches!(dechunk(b"3\r\n{}\n\r\n"), Err(WledJsonApiError::UnexpectedResponse(_))));
+    }
+
+    #[test]
+    fn gate_gives_up() {
+        let gate = Gate::new(2, Instant::now() + Duration::from_millis(50));
+        let started = Instant::now();
+        // the other one never shows up
+        assert!(gate.wait(|| ()));
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert!(!gate.wait(|| panic!("too late to run")));
+    }
+}
@@ -1,4 +1,15 @@
-
 pub mod errors;
 pub mod wled;
-pub mod structures;
\ No newline at end of file
+pub mod builder;
+pub mod websocket;
+pub mod realtime;
+pub mod notifier;
+pub mod audio_sync;
+pub mod discovery;
+pub mod fleet;
+pub mod structures;
+#[cfg(feature = "async")]
+pub mod async_wled;
+
+#[cfg(test)]
+mod test_server;
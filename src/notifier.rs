@@ -0,0 +1,719 @@
+//! WLEDs UDP notifier (sync) protocol, what WLEDs use to keep each other in step
+//! (Settings > Sync Interfaces > WLED Broadcast).
+//!
+//! Packet layout as sent by WLED 0.14 (`notify()` in udp.cpp, version byte 12):
+//! a 41 byte header with the main segment and global state, then 36 bytes per active segment.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::WledJsonApiError;
+use crate::structures::cfg::Cfg;
+use crate::structures::color::{Color, SegColors};
+use crate::structures::relative::RelativeValue;
+use crate::structures::state::{Nl, Seg, State, Udpn};
+
+
+/// `Sync::port0` default
+pub const NOTIFIER_PORT: u16 = 21324;
+/// `Sync::port1` default
+pub const NOTIFIER_PORT2: u16 = 65506;
+/// what this library sends, WLED 0.14
+pub const NOTIFIER_VERSION: u8 = 12;
+
+const HEADER_LEN: usize = 41;
+const SEG_LEN: usize = 36;
+
+
+/// Why a notification was sent, `callMode` in WLED
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallMode {
+    CALL_MODE_INIT,
+    /// change from the UI or an API, what you normally want to send
+    CALL_MODE_DIRECT_CHANGE,
+    CALL_MODE_BUTTON,
+    /// a change that itself came from a notification
+    CALL_MODE_NOTIFICATION,
+    CALL_MODE_NIGHTLIGHT,
+    /// never sent out
+    CALL_MODE_NO_NOTIFY,
+    CALL_MODE_FX_CHANGED,
+    CALL_MODE_HUE,
+    CALL_MODE_PRESET_CYCLE,
+    CALL_MODE_BLYNK,
+    CALL_MODE_ALEXA,
+    CALL_MODE_WS_SEND,
+    CALL_MODE_BUTTON_PRESET,
+    /// something newer than this library
+    CALL_MODE_UNKNOWN(u8),
+}
+
+impl From<u8> for CallMode {
+    fn from(b: u8) -> Self {
+        match b {
+            0 => CallMode::CALL_MODE_INIT,
+            1 => CallMode::CALL_MODE_DIRECT_CHANGE,
+            2 => CallMode::CALL_MODE_BUTTON,
+            3 => CallMode::CALL_MODE_NOTIFICATION,
+            4 => CallMode::CALL_MODE_NIGHTLIGHT,
+            5 => CallMode::CALL_MODE_NO_NOTIFY,
+            6 => CallMode::CALL_MODE_FX_CHANGED,
+            7 => CallMode::CALL_MODE_HUE,
+            8 => CallMode::CALL_MODE_PRESET_CYCLE,
+            9 => CallMode::CALL_MODE_BLYNK,
+            10 => CallMode::CALL_MODE_ALEXA,
+            11 => CallMode::CALL_MODE_WS_SEND,
+            12 => CallMode::CALL_MODE_BUTTON_PRESET,
+            n => CallMode::CALL_MODE_UNKNOWN(n),
+        }
+    }
+}
+
+impl From<CallMode> for u8 {
+    fn from(c: CallMode) -> Self {
+        match c {
+            CallMode::CALL_MODE_INIT => 0,
+            CallMode::CALL_MODE_DIRECT_CHANGE => 1,
+            CallMode::CALL_MODE_BUTTON => 2,
+            CallMode::CALL_MODE_NOTIFICATION => 3,
+            CallMode::CALL_MODE_NIGHTLIGHT => 4,
+            CallMode::CALL_MODE_NO_NOTIFY => 5,
+            CallMode::CALL_MODE_FX_CHANGED => 6,
+            CallMode::CALL_MODE_HUE => 7,
+            CallMode::CALL_MODE_PRESET_CYCLE => 8,
+            CallMode::CALL_MODE_BLYNK => 9,
+            CallMode::CALL_MODE_ALEXA => 10,
+            CallMode::CALL_MODE_WS_SEND => 11,
+            CallMode::CALL_MODE_BUTTON_PRESET => 12,
+            CallMode::CALL_MODE_UNKNOWN(n) => n,
+        }
+    }
+}
+
+
+/// One segment of a notification, 36 bytes on the wire
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifierSegment {
+    pub id: u8,
+    pub start: u16,
+    pub stop: u16,
+    pub grouping: u8,
+    pub spacing: u8,
+    pub offset: u16,
+    /// bit 0 selected, bit 1 reversed, bit 2 on, bit 3 mirrored, bit 7 reversed Y (2D)
+    pub options: u8,
+    /// segment brightness
+    pub opacity: u8,
+    pub effect: u8,
+    pub speed: u8,
+    pub intensity: u8,
+    pub palette: u8,
+    /// primary, secondary, tertiary
+    pub colors: [Color; 3],
+    pub cct: u8,
+    /// bit 0 mirrored Y, bit 1 transposed, bits 2-4 1D to 2D mapping, bits 5-6 sound simulation
+    pub options2: u8,
+    pub custom1: u8,
+    pub custom2: u8,
+    /// 0 to 31
+    pub custom3: u8,
+    pub check1: bool,
+    pub check2: bool,
+    pub check3: bool,
+    pub start_y: u16,
+    pub stop_y: u16,
+}
+
+impl Default for NotifierSegment {
+    fn default() -> Self {
+        NotifierSegment{
+            id: 0,
+            start: 0,
+            stop: 0,
+            grouping: 1,
+            spacing: 0,
+            offset: 0,
+            options: 0b0000_0100,
+            opacity: 255,
+            effect: 0,
+            speed: 128,
+            intensity: 128,
+            palette: 0,
+            colors: [Color::rgbw(0, 0, 0, 0); 3],
+            cct: 127,
+            options2: 0,
+            custom1: 128,
+            custom2: 128,
+            custom3: 16,
+            check1: false,
+            check2: false,
+            check3: false,
+            start_y: 0,
+            stop_y: 1,
+        }
+    }
+}
+
+impl NotifierSegment {
+    pub fn selected(&self) -> bool { self.options & 0x01 != 0 }
+    pub fn reversed(&self) -> bool { self.options & 0x02 != 0 }
+    pub fn on(&self) -> bool { self.options & 0x04 != 0 }
+    pub fn mirrored(&self) -> bool { self.options & 0x08 != 0 }
+
+    /// Everything WLED would take from `seg`, missing fields are WLEDs defaults.
+    /// Relative values (`~`, `r`) can't go in a notification, they're sent as the default too.
+    /// A negative `of` counts back from the end of the segment like WLED does, or is 0 if `seg` has no `start`/`stop`.
+    pub fn from_seg(id: u8, seg: &Seg) -> NotifierSegment {
+        let d = NotifierSegment::default();
+        let flag = |v: Option<bool>, bit: u8, default: bool| if v.unwrap_or(default) { bit } else { 0 };
+        let colors = seg.col.unwrap_or_default().slots();
+        let color = |n: usize| colors[n].map(|c| Color::rgbw(c.r, c.g, c.b, c.w.unwrap_or(0))).unwrap_or(d.colors[n]);
+        let of = seg.of.unwrap_or(0);
+        let offset = u16::try_from(of).unwrap_or_else(|_| match (seg.start, seg.stop) {
+            (Some(start), Some(stop)) if stop > start => {
+                let len = stop - start;
+                (len - of.unsigned_abs() % len) % len
+            }
+            _ => 0,
+        });
+        NotifierSegment{
+            id,
+            start: seg.start.unwrap_or(d.start),
+            stop: seg.stop.unwrap_or(d.stop),
+            grouping: seg.grp.unwrap_or(d.grouping),
+            spacing: seg.spc.unwrap_or(d.spacing),
+            offset,
+            options: flag(seg.sel, 0x01, false) | flag(seg.rev, 0x02, false) | flag(seg.on, 0x04, true) | flag(seg.mi, 0x08, false),
+            opacity: seg.bri.unwrap_or(d.opacity),
+            effect: seg.fx.as_ref().and_then(|v| v.absolute()).map_or(d.effect, |v| *v as u8),
+            speed: seg.sx.as_ref().and_then(|v| v.absolute()).copied().unwrap_or(d.speed),
+            intensity: seg.ix.as_ref().and_then(|v| v.absolute()).copied().unwrap_or(d.intensity),
+            palette: seg.pal.as_ref().and_then(|v| v.absolute()).map_or(d.palette, |v| *v as u8),
+            colors: [color(0), color(1), color(2)],
+            cct: seg.cct.map_or(d.cct, cct_to_byte),
+            options2: seg.si.unwrap_or(0) << 5 | (seg.m12.unwrap_or(0) & 0x07) << 2,
+            custom1: seg.c1.unwrap_or(d.custom1),
+            custom2: seg.c2.unwrap_or(d.custom2),
+            custom3: seg.c3.unwrap_or(d.custom3) & 0x1F,
+            check1: seg.o1.unwrap_or(false),
+            check2: seg.o2.unwrap_or(false),
+            check3: seg.o3.unwrap_or(false),
+            start_y: d.start_y,
+            stop_y: d.stop_y,
+        }
+    }
+
+    pub fn to_seg(&self) -> Seg {
+        Seg{
+            id: Some(self.id as i16),
+            start: Some(self.start),
+            stop: Some(self.stop),
+            grp: Some(self.grouping),
+            spc: Some(self.spacing),
+            of: Some(self.offset as i16),
+            sel: Some(self.selected()),
+            rev: Some(self.reversed()),
+            on: Some(self.on()),
+            mi: Some(self.mirrored()),
+            bri: Some(self.opacity),
+            fx: Some(RelativeValue::Absolute(self.effect as u16)),
+            sx: Some(RelativeValue::Absolute(self.speed)),
+            ix: Some(RelativeValue::Absolute(self.intensity)),
+            pal: Some(RelativeValue::Absolute(self.palette as u16)),
+            col: Some(SegColors::new(Some(self.colors[0]), Some(self.colors[1]), Some(self.colors[2]))),
+            cct: Some(self.cct as u16),
+            c1: Some(self.custom1),
+            c2: Some(self.custom2),
+            c3: Some(self.custom3),
+            o1: Some(self.check1),
+            o2: Some(self.check2),
+            o3: Some(self.check3),
+            si: Some((self.options2 >> 5) & 0x03),
+            m12: Some((self.options2 >> 2) & 0x07),
+            ..Default::default()
+        }
+    }
+
+    fn encode_into(&self, out: &mut [u8]) {
+        out[0] = self.id;
+        out[1..3].copy_from_slice(&self.start.to_be_bytes());
+        out[3..5].copy_from_slice(&self.stop.to_be_bytes());
+        out[5] = self.grouping;
+        out[6] = self.spacing;
+        out[7..9].copy_from_slice(&self.offset.to_be_bytes());
+        out[9] = self.options & 0x8F;
+        out[10] = self.opacity;
+        out[11] = self.effect;
+        out[12] = self.speed;
+        out[13] = self.intensity;
+        out[14] = self.palette;
+        for (n, c) in self.colors.iter().enumerate() {
+            out[15 + n * 4..19 + n * 4].copy_from_slice(&[c.r, c.g, c.b, c.w.unwrap_or(0)]);
+        }
+        out[27] = self.cct;
+        out[28] = self.options2;
+        out[29] = self.custom1;
+        out[30] = self.custom2;
+        out[31] = (self.custom3 & 0x1F) | (self.check1 as u8) << 5 | (self.check2 as u8) << 6 | (self.check3 as u8) << 7;
+        out[32..34].copy_from_slice(&self.start_y.to_be_bytes());
+        out[34..36].copy_from_slice(&self.stop_y.to_be_bytes());
+    }
+
+    fn decode(b: &[u8]) -> NotifierSegment {
+        let color = |n: usize| Color::rgbw(b[15 + n * 4], b[16 + n * 4], b[17 + n * 4], b[18 + n * 4]);
+        NotifierSegment{
+            id: b[0],
+            start: u16::from_be_bytes([b[1], b[2]]),
+            stop: u16::from_be_bytes([b[3], b[4]]),
+            grouping: b[5],
+            spacing: b[6],
+            offset: u16::from_be_bytes([b[7], b[8]]),
+            options: b[9],
+            opacity: b[10],
+            effect: b[11],
+            speed: b[12],
+            intensity: b[13],
+            palette: b[14],
+            colors: [color(0), color(1), color(2)],
+            cct: b[27],
+            options2: b[28],
+            custom1: b[29],
+            custom2: b[30],
+            custom3: b[31] & 0x1F,
+            check1: b[31] & 0x20 != 0,
+            check2: b[31] & 0x40 != 0,
+            check3: b[31] & 0x80 != 0,
+            start_y: u16::from_be_bytes([b[32], b[33]]),
+            stop_y: u16::from_be_bytes([b[34], b[35]]),
+        }
+    }
+}
+
+/// `Seg::cct` is 0 to 255 or a Kelvin value (1900 to 10091), the packet only has room for the first
+fn cct_to_byte(cct: u16) -> u8 {
+    if cct < 1900 {
+        cct.min(255) as u8
+    } else {
+        ((cct.min(10091) - 1900) as u32 * 255 / (10091 - 1900)) as u8
+    }
+}
+
+
+/// One notification. The top level fields are the main segment and the global state,
+/// which is all older WLEDs (version < 11) understand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotifierPacket {
+    pub call_mode: CallMode,
+    pub bri: u8,
+    /// main segment primary, secondary and tertiary color
+    pub colors: [Color; 3],
+    pub nightlight_active: bool,
+    /// nightlight duration in minutes
+    pub nightlight_delay: u8,
+    pub effect: u8,
+    pub speed: u8,
+    pub intensity: u8,
+    pub palette: u8,
+    /// transition time in ms
+    pub transition: u16,
+    /// set on retransmissions
+    pub follow_up: bool,
+    /// effect timebase (ms), effects line up between devices with the same timebase
+    pub timebase: u32,
+    /// where the senders clock comes from (NTP, ...), 0 if it doesn't know the time
+    pub time_source: u8,
+    pub unix_seconds: u32,
+    pub unix_millis: u16,
+    /// bit mapped, a device only takes notifications that share a bit with its receive groups
+    pub sync_groups: u8,
+    /// None if the sender has no CCT LEDs
+    pub cct: Option<u8>,
+    /// protocol version of the sender, 12 for 0.14
+    pub version: u8,
+    pub segments: Vec<NotifierSegment>,
+}
+
+impl Default for NotifierPacket {
+    fn default() -> Self {
+        NotifierPacket{
+            call_mode: CallMode::CALL_MODE_DIRECT_CHANGE,
+            bri: 128,
+            colors: [Color::rgbw(0, 0, 0, 0); 3],
+            nightlight_active: false,
+            nightlight_delay: 60,
+            effect: 0,
+            speed: 128,
+            intensity: 128,
+            palette: 0,
+            transition: 700,
+            follow_up: false,
+            timebase: 0,
+            time_source: 0,
+            unix_seconds: 0,
+            unix_millis: 0,
+            sync_groups: 0x01,
+            cct: None,
+            version: NOTIFIER_VERSION,
+            segments: Vec::new(),
+        }
+    }
+}
+
+impl NotifierPacket {
+    /// What a WLED in `state` would send. The main segment (`state.mainseg`, or the first one) fills in
+    /// the top level fields, `state.seg` the segment list. A light that's off is sent with brightness 0.
+    pub fn from_state(state: &State) -> NotifierPacket {
+        let d = NotifierPacket::default();
+        let segments: Vec<NotifierSegment> = state.seg.as_deref().unwrap_or(&[]).iter().enumerate()
+            .map(|(n, s)| NotifierSegment::from_seg(s.id.map_or(n as u8, |id| id as u8), s))
+            .collect();
+        let main = state.mainseg
+            .and_then(|m| segments.iter().find(|s| s.id == m))
+            .or(segments.first())
+            .cloned()
+            .unwrap_or_default();
+        let nl = state.nl.clone().unwrap_or_default();
+        NotifierPacket{
+            bri: if state.on == Some(false) { 0 } else { state.bri.unwrap_or(d.bri) },
+            colors: main.colors,
+            nightlight_active: nl.on.unwrap_or(false),
+            nightlight_delay: nl.dur.unwrap_or(d.nightlight_delay),
+            effect: main.effect,
+            speed: main.speed,
+            intensity: main.intensity,
+            palette: main.palette,
+            transition: state.transition.map_or(d.transition, |t| t as u16 * 100),
+            sync_groups: state.udpn.as_ref().and_then(|u| u.sgrp).unwrap_or(d.sync_groups),
+            segments,
+            ..d
+        }
+    }
+
+    /// The state patch this notification stands for, what to POST to make a device do the same.
+    /// With no segment data (older senders) the top level fields go to the main segment.
+    pub fn to_state(&self) -> State {
+        let segments = if self.segments.is_empty() {
+            vec![NotifierSegment{
+                colors: self.colors,
+                effect: self.effect,
+                speed: self.speed,
+                intensity: self.intensity,
+                palette: self.palette,
+                ..Default::default()
+            }.to_seg()]
+        } else {
+            self.segments.iter().map(|s| s.to_seg()).collect()
+        };
+        State{
+            on: Some(self.bri > 0),
+            bri: (self.bri > 0).then_some(self.bri),
+            transition: Some((self.transition / 100).min(255) as u8),
+            nl: Some(Nl{ on: Some(self.nightlight_active), dur: Some(self.nightlight_delay), ..Default::default() }),
+            udpn: Some(Udpn{ sgrp: Some(self.sync_groups), ..Default::default() }),
+            seg: Some(segments),
+            ..Default::default()
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0u8; HEADER_LEN + SEG_LEN * self.segments.len()];
+        let [c0, c1, c2] = self.colors;
+        out[0] = 0; // notifier, not one of the realtime protocols
+        out[1] = self.call_mode.into();
+        out[2] = self.bri;
+        out[3..6].copy_from_slice(&[c0.r, c0.g, c0.b]);
+        out[6] = self.nightlight_active as u8;
+        out[7] = self.nightlight_delay;
+        out[8] = self.effect;
+        out[9] = self.speed;
+        out[10] = c0.w.unwrap_or(0);
+        out[11] = self.version;
+        out[12..16].copy_from_slice(&[c1.r, c1.g, c1.b, c1.w.unwrap_or(0)]);
+        out[16] = self.intensity;
+        out[17..19].copy_from_slice(&self.transition.to_le_bytes());
+        out[19] = self.palette;
+        out[20..24].copy_from_slice(&[c2.r, c2.g, c2.b, c2.w.unwrap_or(0)]);
+        out[24] = self.follow_up as u8;
+        out[25..29].copy_from_slice(&self.timebase.to_be_bytes());
+        out[29] = self.time_source;
+        out[30..34].copy_from_slice(&self.unix_seconds.to_be_bytes());
+        out[34..36].copy_from_slice(&self.unix_millis.to_be_bytes());
+        out[36] = self.sync_groups;
+        out[37] = if self.cct.is_some() { 0 } else { 255 };
+        out[38] = self.cct.unwrap_or(0);
+        out[39] = self.segments.len() as u8;
+        out[40] = SEG_LEN as u8;
+        for (n, s) in self.segments.iter().enumerate() {
+            s.encode_into(&mut out[HEADER_LEN + n * SEG_LEN..HEADER_LEN + (n + 1) * SEG_LEN]);
+        }
+        out
+    }
+
+    /// Takes packets from older WLEDs too, fields they don't send keep their defaults.
+    /// `UnexpectedResponse` if it isn't a notification (realtime packets go to the same port).
+    pub fn decode(packet: &[u8]) -> Result<NotifierPacket, WledJsonApiError> {
+        if packet.len() < 12 || packet[0] != 0 {
+            return Err(WledJsonApiError::UnexpectedResponse("not a notifier packet".to_string()));
+        }
+        let d = NotifierPacket::default();
+        let b = |i: usize| packet.get(i).copied();
+        let version = packet[11];
+        let color = |at: usize, w: Option<u8>| Color::rgbw(packet[at], packet[at + 1], packet[at + 2], w.unwrap_or(0));
+        let mut p = NotifierPacket{
+            call_mode: packet[1].into(),
+            bri: packet[2],
+            colors: [
+                color(3, b(10)),
+                if packet.len() >= 16 { color(12, b(15)) } else { d.colors[1] },
+                if packet.len() >= 24 { color(20, b(23)) } else { d.colors[2] },
+            ],
+            nightlight_active: packet[6] != 0,
+            nightlight_delay: packet[7],
+            effect: packet[8],
+            speed: packet[9],
+            intensity: b(16).unwrap_or(d.intensity),
+            palette: b(19).unwrap_or(d.palette),
+            transition: match (b(17), b(18)) {
+                (Some(lo), Some(hi)) => u16::from_le_bytes([lo, hi]),
+                _ => d.transition,
+            },
+            follow_up: b(24).is_some_and(|f| f != 0),
+            version,
+            ..d
+        };
+        if packet.len() >= 29 {
+            p.timebase = u32::from_be_bytes([packet[25], packet[26], packet[27], packet[28]]);
+        }
+        if packet.len() >= 36 {
+            p.time_source = packet[29];
+            p.unix_seconds = u32::from_be_bytes([packet[30], packet[31], packet[32], packet[33]]);
+            p.unix_millis = u16::from_be_bytes([packet[34], packet[35]]);
+        }
+        if let Some(g) = b(36) {
+            p.sync_groups = g;
+        }
+        if let (Some(0), Some(cct)) = (b(37), b(38)) {
+            p.cct = Some(cct);
+        }
+        if version >= 11 && packet.len() >= HEADER_LEN {
+            let count = packet[39] as usize;
+            let size = packet[40] as usize;
+            if size >= SEG_LEN {
+                for n in 0..count {
+                    match packet.get(HEADER_LEN + n * size..HEADER_LEN + n * size + SEG_LEN) {
+                        Some(seg) => p.segments.push(NotifierSegment::decode(seg)),
+                        None => break,
+                    }
+                }
+            }
+        }
+        Ok(p)
+    }
+}
+
+
+/// Sends and receives notifications like another WLED in the sync group would.
+#[derive(Debug)]
+pub struct Notifier {
+    /// listened on, WLED uses port0 and port1
+    pub sockets: Vec<UdpSocket>,
+
+    /// sent to, usually the broadcast address on each port
+    pub targets: Vec<SocketAddr>,
+
+    /// bit mapped, put on everything sent (`Send::grp`)
+    pub send_groups: u8,
+
+    /// bit mapped, notifications that don't share a bit with these are skipped (`Recv::grp`)
+    pub receive_groups: u8,
+
+    /// extra copies sent of every notification (`Send::ret`), flagged as follow ups
+    pub retransmissions: u8,
+
+    /// time between copies, WLED waits 250 ms
+    pub retransmit_interval: Duration,
+}
+
+impl Notifier{
+
+    /// `sockets` get switched to non blocking, `recv` polls them
+    pub fn new(sockets: Vec<UdpSocket>, targets: Vec<SocketAddr>) -> Result<Notifier, WledJsonApiError> {
+        for s in &sockets {
+            s.set_nonblocking(true)?;
+            s.set_broadcast(true)?;
+        }
+        Ok(Notifier{
+            sockets,
+            targets,
+            send_groups: 0x01,
+            receive_groups: 0x01,
+            retransmissions: 0,
+            retransmit_interval: Duration::from_millis(250),
+        })
+    }
+
+    /// listens on every port in `ports` and sends to `broadcast` on each of them
+    pub fn bind(ports: &[u16], broadcast: Ipv4Addr) -> Result<Notifier, WledJsonApiError> {
+        let mut sockets = Vec::new();
+        let mut targets = Vec::new();
+        for port in ports {
+            sockets.push(UdpSocket::bind((Ipv4Addr::UNSPECIFIED, *port))?);
+            targets.push(SocketAddr::new(IpAddr::V4(broadcast), *port));
+        }
+        Notifier::new(sockets, targets)
+    }
+
+    /// Same ports, groups and retransmissions as the device `cfg` came from (`cfg.if.sync`),
+    /// so this acts like one more WLED configured the same way.
+    /// `broadcast` is the broadcast address of the network (192.168.1.255 for example).
+    pub fn from_cfg(cfg: &Cfg, broadcast: Ipv4Addr) -> Result<Notifier, WledJsonApiError> {
+        let sync = cfg.if_field.as_ref().and_then(|i| i.sync.as_ref()).ok_or(WledJsonApiError::MissingKey)?;
+        let mut ports = vec![sync.port0.unwrap_or(NOTIFIER_PORT)];
+        // 0 turns the supplemental port off
+        match sync.port1.unwrap_or(NOTIFIER_PORT2) {
+            0 => {}
+            p if p == ports[0] => {}
+            p => ports.push(p),
+        }
+        let mut notifier = Notifier::bind(&ports, broadcast)?;
+        if let Some(send) = &sync.send {
+            notifier.send_groups = send.grp.unwrap_or(notifier.send_groups);
+            notifier.retransmissions = send.ret.unwrap_or(0);
+        }
+        if let Some(recv) = &sync.recv {
+            notifier.receive_groups = recv.grp.unwrap_or(notifier.receive_groups);
+        }
+        Ok(notifier)
+    }
+
+    /// Sends `packet` (with `send_groups`) to every target, then the retransmissions.
+    /// Blocks for `retransmissions * retransmit_interval`.
+    pub fn send(&self, packet: &NotifierPacket) -> Result<(), WledJsonApiError> {
+        let packets: Vec<(SocketAddr, NotifierPacket)> = self.targets.iter().map(|t| (*t, packet.clone())).collect();
+        self.send_each(&packets)
+    }
+
+    /// Like `send`, but every packet goes to its own address instead of the targets
+    /// (a different state for each device, straight to it). All of them go out before each retransmission.
+    /// With nothing to send it's `Ok` straight away, even without a socket.
+    pub fn send_each(&self, packets: &[(SocketAddr, NotifierPacket)]) -> Result<(), WledJsonApiError> {
+        if packets.is_empty() {
+            return Ok(());
+        }
+        let socket = self.sockets.first()
+            .ok_or_else(|| WledJsonApiError::InvalidValue("notifier has no socket to send from".to_string()))?;
+        let mut packets = packets.to_vec();
+        for n in 0..=self.retransmissions {
+            if n > 0 {
+                thread::sleep(self.retransmit_interval);
+            }
+            for (target, packet) in packets.iter_mut() {
+                packet.sync_groups = self.send_groups;
+                packet.follow_up |= n > 0;
+                socket.send_to(&packet.encode(), *target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Next notification for one of our receive groups, from any port.
+    /// Retransmissions come through as well, check `follow_up`.
+    /// None waits forever, otherwise an io `TimedOut` error once `timeout` is up.
+    pub fn recv(&self, timeout: Option<Duration>) -> Result<(SocketAddr, NotifierPacket), WledJsonApiError> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut buf = [0u8; 1472];
+        loop {
+            for socket in &self.sockets {
+                match socket.recv_from(&mut buf) {
+                    Ok((len, from)) => {
+                        if let Ok(packet) = NotifierPacket::decode(&buf[..len]) {
+                            if packet.sync_groups & self.receive_groups != 0 {
+                                return Ok((from, packet));
+                            }
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if deadline.is_some_and(|d| Instant::now() >= d) {
+                return Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into());
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let state = State::try_from(r#"{"on":true,"bri":200,"transition":7,"mainseg":1,"udpn":{"sgrp":2},"seg":[{"id":0,"start":0,"stop":10,"fx":0},{"id":1,"start":10,"stop":30,"col":[[255,160,0],[0,0,0,20]],"fx":9,"sx":50,"ix":60,"pal":4,"rev":true,"c3":31,"o2":true}]}"#).unwrap();
+        let packet = NotifierPacket::from_state(&state);
+        let bytes = packet.encode();
+        assert_eq!(bytes.len(), 41 + 2 * 36);
+        assert_eq!(&bytes[..12], &[0, 1, 200, 255, 160, 0, 0, 60, 9, 50, 0, 12]);
+        assert_eq!(&bytes[12..20], &[0, 0, 0, 20, 60, 0xBC, 0x02, 4]);
+        assert_eq!(&bytes[36..41], &[2, 255, 0, 2, 36]);
+        let seg1 = &bytes[41 + 36..];
+        assert_eq!(&seg1[..5], &[1, 0, 10, 0, 30]);
+        assert_eq!(seg1[9], 0b0000_0110);
+        assert_eq!(seg1[31], 31 | 0x40);
+
+        let decoded = NotifierPacket::decode(&bytes).unwrap();
+        assert_eq!(decoded, packet);
+        let back = decoded.to_state();
+        assert_eq!(back.seg.as_ref().unwrap()[1].fx, Some(RelativeValue::Absolute(9)));
+        assert_eq!(back.transition, Some(7));
+
+        // a version 9 packet (no per segment data) still decodes
+        let old = NotifierPacket::decode(&bytes[..37]).unwrap();
+        assert!(old.segments.is_empty());
+        assert_eq!(old.sync_groups, 2);
+        assert!(NotifierPacket::decode(&[2, 1, 255, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn send_and_recv() {
+        let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let rx_addr = rx.local_addr().unwrap();
+        let mut receiver = Notifier::new(vec![rx], vec![]).unwrap();
+        receiver.receive_groups = 0b0000_0110;
+
+        let mut sender = Notifier::new(vec![UdpSocket::bind("127.0.0.1:0").unwrap()], vec![rx_addr]).unwrap();
+        sender.send_groups = 0b0000_0001;
+        sender.send(&NotifierPacket{ bri: 1, ..Default::default() }).unwrap();
+        sender.send_groups = 0b0000_0100;
+        sender.retransmissions = 1;
+        sender.retransmit_interval = Duration::from_millis(1);
+        sender.send(&NotifierPacket{ bri: 2, ..Default::default() }).unwrap();
+
+        // group 1 isn't one of ours, so the first one is skipped
+        let (_, first) = receiver.recv(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!((first.bri, first.follow_up), (2, false));
+        let (_, again) = receiver.recv(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!((again.bri, again.follow_up), (2, true));
+        assert!(receiver.recv(Some(Duration::from_millis(20))).is_err());
+
+        let deaf = Notifier::new(vec![], vec![rx_addr]).unwrap();
+        assert!(matches!(deaf.send(&NotifierPacket::default()), Err(WledJsonApiError::InvalidValue(_))));
+        // nothing to send to, so not having a socket doesn't matter
+        let idle = Notifier::new(vec![], vec![]).unwrap();
+        assert!(idle.send(&NotifierPacket::default()).is_ok());
+        assert!(idle.send_each(&[]).is_ok());
+    }
+
+    #[test]
+    fn negative_offset() {
+        let seg = |start, stop, of| Seg{ start, stop, of: Some(of), ..Default::default() };
+        assert_eq!(NotifierSegment::from_seg(0, &seg(Some(10), Some(40), 5)).offset, 5);
+        assert_eq!(NotifierSegment::from_seg(0, &seg(Some(10), Some(40), -5)).offset, 25);
+        assert_eq!(NotifierSegment::from_seg(0, &seg(Some(10), Some(40), -30)).offset, 0);
+        assert_eq!(NotifierSegment::from_seg(0, &seg(None, None, -5)).offset, 0);
+    }
+}
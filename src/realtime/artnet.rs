@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::{bind_for, resolve};
+use crate::realtime::dmx::DmxLayout;
+use crate::structures::color::Color;
+use crate::wled::Wled;
+
+
+/// WLED switches to Art-Net when its realtime port is set to this
+pub const ARTNET_PORT: u16 = 6454;
+
+const ARTNET_ID: [u8; 8] = *b"Art-Net\0";
+const ARTNET_PROTOCOL_VERSION: u16 = 14;
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const ARTDMX_HEADER_LEN: usize = 18;
+const ARTPOLLREPLY_LEN: usize = 239;
+
+
+/// ID, little endian opcode, then (for everything but ArtPollReply) the big endian protocol version
+fn header(op: u16, with_version: bool) -> Vec<u8> {
+    let mut packet = ARTNET_ID.to_vec();
+    packet.extend_from_slice(&op.to_le_bytes());
+    if with_version {
+        packet.extend_from_slice(&ARTNET_PROTOCOL_VERSION.to_be_bytes());
+    }
+    packet
+}
+
+/// the opcode, if `data` is an Art-Net packet at all
+fn opcode(data: &[u8]) -> Option<u16> {
+    if data.len() < 10 || data[..8] != ARTNET_ID {
+        return None;
+    }
+    Some(u16::from_le_bytes([data[8], data[9]]))
+}
+
+fn fixed_str(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).to_string()
+}
+
+fn put_str(dest: &mut [u8], s: &str) {
+    // always leave the last byte as the terminating 0
+    let len = s.len().min(dest.len() - 1);
+    dest[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+
+/// ArtDmx, one universe of DMX data
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ArtDmx {
+    /// 1 to 255, 0 turns off reordering on the receiving end
+    pub sequence: u8,
+    pub physical: u8,
+    /// 15 bit port address; net (7 bits), sub-net (4 bits) and universe (4 bits).
+    /// WLED compares the whole thing to its universe setting
+    pub universe: u16,
+    /// up to 512 channels, channel 1 first
+    pub data: Vec<u8>,
+}
+
+impl ArtDmx {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = self.data.clone();
+        data.truncate(512);
+        // the length has to be even, and at least 2
+        if data.len() % 2 == 1 || data.is_empty() {
+            data.push(0);
+        }
+        let mut packet = header(OP_DMX, true);
+        packet.push(self.sequence);
+        packet.push(self.physical);
+        packet.extend_from_slice(&(self.universe & 0x7FFF).to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&data);
+        packet
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<ArtDmx, WledJsonApiError> {
+        if opcode(packet) != Some(OP_DMX) || packet.len() < ARTDMX_HEADER_LEN {
+            return Err(WledJsonApiError::UnexpectedResponse("not an ArtDmx packet".to_string()));
+        }
+        let len = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+        let data = packet.get(ARTDMX_HEADER_LEN..ARTDMX_HEADER_LEN + len)
+            .ok_or_else(|| WledJsonApiError::UnexpectedResponse("ArtDmx shorter than its length".to_string()))?;
+        Ok(ArtDmx{
+            sequence: packet[12],
+            physical: packet[13],
+            universe: u16::from_le_bytes([packet[14], packet[15]]) & 0x7FFF,
+            data: data.to_vec(),
+        })
+    }
+}
+
+
+/// ArtPoll, asks every node on the network to answer with an ArtPollReply
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArtPoll {
+    /// TalkToMe bits, 0 is fine for a one off poll
+    pub flags: u8,
+    pub diag_priority: u8,
+}
+
+impl ArtPoll {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = header(OP_POLL, true);
+        packet.push(self.flags);
+        packet.push(self.diag_priority);
+        packet
+    }
+
+    pub fn decode(packet: &[u8]) -> Result<ArtPoll, WledJsonApiError> {
+        if opcode(packet) != Some(OP_POLL) || packet.len() < 14 {
+            return Err(WledJsonApiError::UnexpectedResponse("not an ArtPoll packet".to_string()));
+        }
+        Ok(ArtPoll{ flags: packet[12], diag_priority: packet[13] })
+    }
+
+    /// Broadcasts a poll from `bind` to `broadcast` and collects replies for `wait`.
+    ///
+    /// Nodes (WLED included) answer to port 6454 of whoever polled,
+    /// so `bind` usually has to be `0.0.0.0:6454`, which fails if something else already has that port.
+    pub fn discover(bind: SocketAddr, broadcast: SocketAddr, wait: Duration) -> Result<Vec<(SocketAddr, ArtPollReply)>, WledJsonApiError> {
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_broadcast(true)?;
+        socket.send_to(&ArtPoll::default().encode(), broadcast)?;
+
+        let deadline = Instant::now() + wait;
+        let mut replies = Vec::new();
+        let mut buf = [0u8; 1024];
+        while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+            socket.set_read_timeout(Some(left))?;
+            match socket.recv_from(&mut buf) {
+                // other traffic on 6454 (our own poll, ArtDmx) is skipped
+                Ok((len, from)) => if let Ok(reply) = ArtPollReply::decode(&buf[..len]) {
+                    replies.push((from, reply));
+                },
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(replies)
+    }
+}
+
+
+/// ArtPollReply, what a node says about itself. Only the fields worth knowing about are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtPollReply {
+    pub ip: Ipv4Addr,
+    pub port: u16,
+    /// firmware version of the node
+    pub version: u16,
+    /// bits 14-8 of the port addresses
+    pub net_switch: u8,
+    /// bits 7-4 of the port addresses
+    pub sub_switch: u8,
+    pub oem: u16,
+    pub status1: u8,
+    pub esta_man: u16,
+    /// up to 17 characters, WLED puts the server description here
+    pub short_name: String,
+    /// up to 63 characters
+    pub long_name: String,
+    pub node_report: String,
+    pub num_ports: u16,
+    pub port_types: [u8; 4],
+    /// bits 3-0 of the output port addresses
+    pub sw_out: [u8; 4],
+    pub mac: [u8; 6],
+    pub status2: u8,
+}
+
+impl Default for ArtPollReply {
+    fn default() -> Self {
+        ArtPollReply{
+            ip: Ipv4Addr::UNSPECIFIED,
+            port: ARTNET_PORT,
+            version: 0,
+            net_switch: 0,
+            sub_switch: 0,
+            oem: 0,
+            status1: 0,
+            esta_man: 0,
+            short_name: String::new(),
+            long_name: String::new(),
+            node_report: String::new(),
+            num_ports: 0,
+            port_types: [0; 4],
+            sw_out: [0; 4],
+            mac: [0; 6],
+            status2: 0,
+        }
+    }
+}
+
+impl ArtPollReply {
+    /// first universe (port address) of output port `port`
+    pub fn universe(&self, port: usize) -> u16 {
+        ((self.net_switch as u16 & 0x7F) << 8) | ((self.sub_switch as u16 & 0x0F) << 4) | (self.sw_out[port % 4] as u16 & 0x0F)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = header(OP_POLL_REPLY, false);
+        packet.resize(ARTPOLLREPLY_LEN, 0);
+        packet[10..14].copy_from_slice(&self.ip.octets());
+        packet[14..16].copy_from_slice(&self.port.to_le_bytes());
+        packet[16..18].copy_from_slice(&self.version.to_be_bytes());
+        packet[18] = self.net_switch;
+        packet[19] = self.sub_switch;
+        packet[20..22].copy_from_slice(&self.oem.to_be_bytes());
+        packet[23] = self.status1;
+        packet[24..26].copy_from_slice(&self.esta_man.to_le_bytes());
+        put_str(&mut packet[26..44], &self.short_name);
+        put_str(&mut packet[44..108], &self.long_name);
+        put_str(&mut packet[108..172], &self.node_report);
+        packet[172..174].copy_from_slice(&self.num_ports.to_be_bytes());
+        packet[174..178].copy_from_slice(&self.port_types);
+        packet[190..194].copy_from_slice(&self.sw_out);
+        packet[201..207].copy_from_slice(&self.mac);
+        packet[212] = self.status2;
+        packet
+    }
+
+    /// older nodes send shorter replies, anything past the MAC address is optional
+    pub fn decode(packet: &[u8]) -> Result<ArtPollReply, WledJsonApiError> {
+        if opcode(packet) != Some(OP_POLL_REPLY) || packet.len() < 207 {
+            return Err(WledJsonApiError::UnexpectedResponse("not an ArtPollReply packet".to_string()));
+        }
+        let mut reply = ArtPollReply{
+            ip: Ipv4Addr::new(packet[10], packet[11], packet[12], packet[13]),
+            port: u16::from_le_bytes([packet[14], packet[15]]),
+            version: u16::from_be_bytes([packet[16], packet[17]]),
+            net_switch: packet[18],
+            sub_switch: packet[19],
+            oem: u16::from_be_bytes([packet[20], packet[21]]),
+            status1: packet[23],
+            esta_man: u16::from_le_bytes([packet[24], packet[25]]),
+            short_name: fixed_str(&packet[26..44]),
+            long_name: fixed_str(&packet[44..108]),
+            node_report: fixed_str(&packet[108..172]),
+            num_ports: u16::from_be_bytes([packet[172], packet[173]]),
+            ..Default::default()
+        };
+        reply.port_types.copy_from_slice(&packet[174..178]);
+        reply.sw_out.copy_from_slice(&packet[190..194]);
+        reply.mac.copy_from_slice(&packet[201..207]);
+        reply.status2 = packet.get(212).copied().unwrap_or(0);
+        Ok(reply)
+    }
+}
+
+
+/// Streams frames as ArtDmx, laid out per the devices DMX settings (see `DmxLayout`), same as `E131Sender`.
+#[derive(Debug)]
+pub struct ArtNetSender {
+    pub socket: UdpSocket,
+    pub target: SocketAddr,
+    pub layout: DmxLayout,
+    /// per universe, 1 to 255
+    sequences: BTreeMap<u16, u8>,
+}
+
+impl ArtNetSender{
+
+    pub fn new(target: SocketAddr, layout: DmxLayout) -> Result<ArtNetSender, WledJsonApiError> {
+        Ok(ArtNetSender{
+            socket: bind_for(&target)?,
+            target,
+            layout,
+            sequences: BTreeMap::new(),
+        })
+    }
+
+    /// Sends to the same host as `wled` on the Art-Net port, laid out from `cfg.if.live.dmx`.
+    /// `wled.cfg` has to be fetched first (`MissingKey` if not).
+    pub fn from_wled(wled: &Wled) -> Result<ArtNetSender, WledJsonApiError> {
+        let cfg = wled.cfg.as_ref().ok_or(WledJsonApiError::MissingKey)?;
+        ArtNetSender::new(resolve(&wled.url, ARTNET_PORT)?, DmxLayout::from_cfg(cfg)?)
+    }
+
+    /// the packets `colors` turns into, without sending them
+    pub fn encode(&mut self, colors: &[Color]) -> Result<Vec<Vec<u8>>, WledJsonApiError> {
+        Ok(self.layout.universes(colors)?.into_iter().map(|(universe, data)| {
+            let sequence = self.sequences.entry(universe).or_insert(0);
+            *sequence = *sequence % 255 + 1;
+            ArtDmx{ sequence: *sequence, physical: 0, universe, data }.encode()
+        }).collect())
+    }
+
+    /// sends one frame, LED 0 first. `UnsupportedDmxMode` if the layout can't take frames
+    pub fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        for packet in self.encode(colors)? {
+            self.socket.send_to(&packet, self.target)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use crate::structures::cfg::cfg_if2::DmxMode;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let layout = DmxLayout{ mode: DmxMode::DMX_MODE_MULTIPLE_RGB, universe: 0x123, address: 1, dimmer: 255 };
+        let mut sender = ArtNetSender::new("127.0.0.1:6454".parse().unwrap(), layout).unwrap();
+        let packets = sender.encode(&[Color::rgb(1, 2, 3)]).unwrap();
+        assert_eq!(packets[0], vec![
+            b'A', b'r', b't', b'-', b'N', b'e', b't', 0, 0x00, 0x50, 0, 14, 1, 0, 0x23, 0x01, 0, 4, 1, 2, 3, 0,
+        ]);
+        let dmx = ArtDmx::decode(&packets[0]).unwrap();
+        assert_eq!((dmx.universe, dmx.sequence, dmx.data.len()), (0x123, 1, 4));
+
+        let reply = ArtPollReply{
+            ip: Ipv4Addr::new(10, 0, 0, 7),
+            short_name: "WLED".to_string(),
+            long_name: "WLED kitchen".to_string(),
+            net_switch: 1,
+            sub_switch: 2,
+            sw_out: [3, 0, 0, 0],
+            mac: [1, 2, 3, 4, 5, 6],
+            ..Default::default()
+        };
+        let encoded = reply.encode();
+        assert_eq!(encoded.len(), 239);
+        assert_eq!(ArtPollReply::decode(&encoded).unwrap(), reply);
+        assert_eq!(reply.universe(0), 0x123);
+        assert!(ArtPoll::decode(&ArtPoll::default().encode()).is_ok());
+    }
+
+    #[test]
+    fn discover() {
+        let node = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let node_addr = node.local_addr().unwrap();
+        let answer = thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (len, from) = node.recv_from(&mut buf).unwrap();
+            ArtPoll::decode(&buf[..len]).unwrap();
+            let reply = ArtPollReply{ short_name: "WLED".to_string(), ..Default::default() };
+            node.send_to(&reply.encode(), from).unwrap();
+        });
+
+        let replies = ArtPoll::discover("127.0.0.1:0".parse().unwrap(), node_addr, Duration::from_millis(500)).unwrap();
+        answer.join().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].0, node_addr);
+        assert_eq!(replies[0].1.short_name, "WLED");
+    }
+}
@@ -0,0 +1,118 @@
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::{bind_for, pixel_bytes, resolve, PixelFormat};
+use crate::structures::color::Color;
+use crate::wled::Wled;
+
+
+/// WLED listens for DDP here, always
+pub const DDP_PORT: u16 = 4048;
+
+/// most data bytes WLED takes in one packet, 480 RGB or 360 RGBW LEDs
+pub const DDP_MAX_DATA: usize = 1440;
+
+const DDP_HEADER_LEN: usize = 10;
+const DDP_FLAGS_VER1: u8 = 0x40;
+const DDP_FLAGS_PUSH: u8 = 0x01;
+const DDP_TYPE_RGB24: u8 = 0x0B;
+const DDP_TYPE_RGBW32: u8 = 0x1B;
+const DDP_ID_DISPLAY: u8 = 1;
+
+
+/// Sends frames over [DDP](http://www.3waylabs.com/ddp/).
+///
+/// Frames longer than one packet are split up, every packet says where its data goes (byte offset),
+/// and the last one has the push flag set so WLED shows the frame once it's all there.
+#[derive(Debug)]
+pub struct DdpSender {
+    pub socket: UdpSocket,
+    pub target: SocketAddr,
+    pub format: PixelFormat,
+    /// 1 to 15, goes up with every packet
+    sequence: u8,
+}
+
+impl DdpSender{
+
+    pub fn new(target: SocketAddr, format: PixelFormat) -> Result<DdpSender, WledJsonApiError> {
+        Ok(DdpSender{
+            socket: bind_for(&target)?,
+            target,
+            format,
+            sequence: 0,
+        })
+    }
+
+    /// Sends to the same host as `wled`, on the DDP port.
+    /// RGBW if `wled.info` has been fetched and says there's a white channel, RGB otherwise.
+    pub fn from_wled(wled: &Wled) -> Result<DdpSender, WledJsonApiError> {
+        let format = wled.info.as_ref().map(PixelFormat::from_info).unwrap_or_default();
+        DdpSender::new(resolve(&wled.url, DDP_PORT)?, format)
+    }
+
+    /// the packets `colors` turns into, without sending them
+    pub fn encode(&mut self, colors: &[Color]) -> Vec<Vec<u8>> {
+        let data = pixel_bytes(colors, self.format);
+        // keep whole LEDs in every packet
+        let per_packet = DDP_MAX_DATA - DDP_MAX_DATA % self.format.channels();
+        let data_type = match self.format {
+            PixelFormat::Rgb => DDP_TYPE_RGB24,
+            PixelFormat::Rgbw => DDP_TYPE_RGBW32,
+        };
+
+        let chunks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(per_packet).collect() };
+        let last = chunks.len() - 1;
+        chunks.into_iter().enumerate().map(|(n, chunk)| {
+            self.sequence = self.sequence % 15 + 1;
+            let mut packet = Vec::with_capacity(DDP_HEADER_LEN + chunk.len());
+            packet.push(if n == last { DDP_FLAGS_VER1 | DDP_FLAGS_PUSH } else { DDP_FLAGS_VER1 });
+            packet.push(self.sequence);
+            packet.push(data_type);
+            packet.push(DDP_ID_DISPLAY);
+            packet.extend_from_slice(&((n * per_packet) as u32).to_be_bytes());
+            packet.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            packet.extend_from_slice(chunk);
+            packet
+        }).collect()
+    }
+
+    /// sends one frame, LED 0 first
+    pub fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        for packet in self.encode(colors) {
+            self.socket.send_to(&packet, self.target)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sender = DdpSender::new(receiver.local_addr().unwrap(), PixelFormat::Rgb).unwrap();
+
+        // 500 LEDs is 1500 bytes, one more than fits in a packet
+        let frame: Vec<Color> = (0..500).map(|n| Color::rgb(n as u8, 0, 0)).collect();
+        sender.send(&frame).unwrap();
+
+        let mut buf = [0u8; 2000];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(len, 10 + 1440);
+        assert_eq!(&buf[..10], &[0x40, 1, 0x0B, 1, 0, 0, 0, 0, 0x05, 0xA0]);
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..10], &[0x41, 2, 0x0B, 1, 0, 0, 0x05, 0xA0, 0, 60]);
+        assert_eq!(buf[10], 480u16 as u8);
+        assert_eq!(len, 70);
+
+        sender.format = PixelFormat::Rgbw;
+        let packets = sender.encode(&[Color::rgbw(1, 2, 3, 4), Color::rgb(5, 6, 7)]);
+        assert_eq!(packets, vec![vec![0x41, 3, 0x1B, 1, 0, 0, 0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 0]]);
+    }
+}
@@ -0,0 +1,128 @@
+use crate::errors::WledJsonApiError;
+use crate::structures::cfg::Cfg;
+use crate::structures::cfg::cfg_if2::{DmxMode, LiveDmx};
+use crate::structures::color::Color;
+
+
+pub const CHANNELS_PER_UNIVERSE: usize = 512;
+
+
+/// Where a frame goes in DMX land, the way WLED reads it back out (`handleDMXData` in e131.cpp).
+/// Shared by the E1.31 and Art-Net senders.
+///
+/// Only the modes that take per LED (or whole strip) colors can be streamed to:
+/// `DMX_MODE_SINGLE_RGB`, `DMX_MODE_SINGLE_DRGB`, `DMX_MODE_MULTIPLE_RGB`, `DMX_MODE_MULTIPLE_DRGB`
+/// and `DMX_MODE_MULTIPLE_RGBW`. The effect and preset modes are remote controls, not frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DmxLayout {
+    pub mode: DmxMode,
+
+    /// first universe, the `MULTIPLE` modes carry on into the ones after it
+    pub universe: u16,
+
+    /// 1 to 512; DMX address (channel) the data starts at, in the first universe only
+    pub address: u16,
+
+    /// master brightness sent in the dimmer channel of the `DRGB` modes
+    pub dimmer: u8,
+}
+
+impl DmxLayout {
+    /// `uni`, `addr` and `mode` from the devices DMX settings, missing ones are WLEDs defaults
+    pub fn from_live_dmx(dmx: &LiveDmx) -> DmxLayout {
+        DmxLayout{
+            mode: dmx.mode.clone().unwrap_or(DmxMode::DMX_MODE_MULTIPLE_RGB),
+            universe: dmx.uni.unwrap_or(1),
+            address: dmx.addr.unwrap_or(1),
+            dimmer: 255,
+        }
+    }
+
+    /// `from_live_dmx` with `cfg.if.live.dmx`, `MissingKey` if the cfg doesn't have it
+    pub fn from_cfg(cfg: &Cfg) -> Result<DmxLayout, WledJsonApiError> {
+        cfg.if_field.as_ref()
+            .and_then(|i| i.live.as_ref())
+            .and_then(|l| l.dmx.as_ref())
+            .map(DmxLayout::from_live_dmx)
+            .ok_or(WledJsonApiError::MissingKey)
+    }
+
+    /// DMX channels used by every LED, 0 for the `SINGLE` modes
+    fn channels_per_led(&self) -> Result<usize, WledJsonApiError> {
+        match self.mode {
+            DmxMode::DMX_MODE_SINGLE_RGB | DmxMode::DMX_MODE_SINGLE_DRGB => Ok(0),
+            DmxMode::DMX_MODE_MULTIPLE_RGB | DmxMode::DMX_MODE_MULTIPLE_DRGB => Ok(3),
+            DmxMode::DMX_MODE_MULTIPLE_RGBW => Ok(4),
+            _ => Err(WledJsonApiError::UnsupportedDmxMode(self.mode.clone())),
+        }
+    }
+
+    /// LEDs that fit in universes after the first one, 170 RGB or 128 RGBW
+    pub fn leds_per_universe(&self) -> Result<usize, WledJsonApiError> {
+        Ok(CHANNELS_PER_UNIVERSE / self.channels_per_led()?.max(1))
+    }
+
+    /// Lays a frame out over as many universes as it needs.
+    /// Each universe comes with its channel values, DMX channel 1 first, only as many as are used.
+    pub fn universes(&self, colors: &[Color]) -> Result<Vec<(u16, Vec<u8>)>, WledJsonApiError> {
+        let per_led = self.channels_per_led()?;
+        if self.address == 0 || self.address as usize > CHANNELS_PER_UNIVERSE {
+            return Err(WledJsonApiError::InvalidValue(format!("DMX address {}", self.address)));
+        }
+        let mut first = vec![0u8; self.address as usize - 1];
+        if matches!(self.mode, DmxMode::DMX_MODE_SINGLE_DRGB | DmxMode::DMX_MODE_MULTIPLE_DRGB) {
+            first.push(self.dimmer);
+        }
+
+        if per_led == 0 {
+            let c = colors.first().copied().unwrap_or(Color::BLACK);
+            first.extend_from_slice(&[c.r, c.g, c.b]);
+            first.truncate(CHANNELS_PER_UNIVERSE);
+            return Ok(vec![(self.universe, first)]);
+        }
+
+        let led_bytes = |c: &Color| {
+            let mut b = vec![c.r, c.g, c.b];
+            if per_led == 4 {
+                b.push(c.w.unwrap_or(0));
+            }
+            b
+        };
+        let in_first = CHANNELS_PER_UNIVERSE.saturating_sub(first.len()) / per_led;
+        let split = in_first.min(colors.len());
+        first.extend(colors[..split].iter().flat_map(led_bytes));
+
+        let mut universes = vec![(self.universe, first)];
+        for (n, chunk) in colors[split..].chunks(CHANNELS_PER_UNIVERSE / per_led).enumerate() {
+            universes.push((self.universe.wrapping_add(n as u16 + 1), chunk.iter().flat_map(led_bytes).collect()));
+        }
+        Ok(universes)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let mut layout = DmxLayout{ mode: DmxMode::DMX_MODE_MULTIPLE_RGB, universe: 3, address: 4, dimmer: 200 };
+        let frame = vec![Color::rgb(1, 2, 3); 400];
+        let u = layout.universes(&frame).unwrap();
+        // 169 LEDs fit behind address 4, then 170 and the 61 left over
+        assert_eq!(u.iter().map(|(n, c)| (*n, c.len())).collect::<Vec<_>>(), vec![(3, 510), (4, 510), (5, 183)]);
+        assert_eq!(&u[0].1[..5], &[0, 0, 0, 1, 2]);
+
+        layout.mode = DmxMode::DMX_MODE_SINGLE_DRGB;
+        layout.address = 1;
+        assert_eq!(layout.universes(&frame).unwrap(), vec![(3, vec![200, 1, 2, 3])]);
+
+        layout.mode = DmxMode::DMX_MODE_MULTIPLE_RGBW;
+        assert_eq!(layout.leds_per_universe().unwrap(), 128);
+        assert_eq!(layout.universes(&[Color::rgbw(1, 2, 3, 4)]).unwrap(), vec![(3, vec![1, 2, 3, 4])]);
+
+        layout.mode = DmxMode::DMX_MODE_PRESET;
+        assert!(matches!(layout.universes(&frame), Err(WledJsonApiError::UnsupportedDmxMode(_))));
+    }
+}
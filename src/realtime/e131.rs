@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::{bind_for, resolve};
+use crate::realtime::dmx::DmxLayout;
+use crate::structures::color::Color;
+use crate::wled::Wled;
+
+
+pub const E131_PORT: u16 = 5568;
+
+/// priority sent when the device doesn't ask for one, the E1.31 default
+pub const E131_DEFAULT_PRIORITY: u8 = 100;
+
+const ACN_PACKET_IDENTIFIER: [u8; 12] = *b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+/// everything before the DMX data, start code included
+const E131_HEADER_LEN: usize = 126;
+
+
+/// where the packets go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E131Destination {
+    /// straight to the device
+    Unicast(SocketAddr),
+    /// 239.255.{universe high byte}.{universe low byte}, on this port
+    Multicast(u16),
+}
+
+impl E131Destination {
+    pub fn for_universe(&self, universe: u16) -> SocketAddr {
+        match self {
+            E131Destination::Unicast(a) => *a,
+            E131Destination::Multicast(port) => {
+                let [hi, lo] = universe.to_be_bytes();
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, hi, lo)), *port)
+            }
+        }
+    }
+}
+
+
+/// Streams frames over E1.31 (sACN), laid out per the devices DMX settings (see `DmxLayout`).
+#[derive(Debug)]
+pub struct E131Sender {
+    pub socket: UdpSocket,
+    pub destination: E131Destination,
+    pub layout: DmxLayout,
+
+    /// 0 to 200; WLED drops packets below its `e131prio` when that's set
+    pub priority: u8,
+
+    /// shows up in sACN viewers, at most 63 bytes are sent
+    pub source_name: String,
+
+    /// component identifier, random for every sender
+    pub cid: [u8; 16],
+
+    /// per universe, like the standard wants
+    sequences: BTreeMap<u16, u8>,
+}
+
+impl E131Sender{
+
+    pub fn new(destination: E131Destination, layout: DmxLayout) -> Result<E131Sender, WledJsonApiError> {
+        let socket = match destination {
+            E131Destination::Unicast(a) => bind_for(&a)?,
+            E131Destination::Multicast(_) => UdpSocket::bind("0.0.0.0:0")?,
+        };
+        let mut cid = [0u8; 16];
+        for half in cid.chunks_mut(8) {
+            half.copy_from_slice(&RandomState::new().build_hasher().finish().to_be_bytes());
+        }
+        Ok(E131Sender{
+            socket,
+            destination,
+            layout,
+            priority: E131_DEFAULT_PRIORITY,
+            source_name: "wled-json-api-library".to_string(),
+            cid,
+            sequences: BTreeMap::new(),
+        })
+    }
+
+    /// Everything from the devices realtime settings, so `wled.cfg` has to be fetched first (`MissingKey` if not).
+    /// Universe, address and mode from `cfg.if.live.dmx`, multicast if `mc` is on, the port from `port`
+    /// and the priority from `e131prio`.
+    pub fn from_wled(wled: &Wled) -> Result<E131Sender, WledJsonApiError> {
+        let cfg = wled.cfg.as_ref().ok_or(WledJsonApiError::MissingKey)?;
+        let layout = DmxLayout::from_cfg(cfg)?;
+        let live = cfg.if_field.as_ref().and_then(|i| i.live.as_ref()).ok_or(WledJsonApiError::MissingKey)?;
+        let port = live.port.unwrap_or(E131_PORT);
+        let destination = if live.mc == Some(true) {
+            E131Destination::Multicast(port)
+        } else {
+            E131Destination::Unicast(resolve(&wled.url, port)?)
+        };
+        let mut sender = E131Sender::new(destination, layout)?;
+        if let Some(p) = live.dmx.as_ref().and_then(|d| d.e131prio).filter(|p| *p != 0) {
+            sender.priority = p;
+        }
+        Ok(sender)
+    }
+
+    /// one data packet for `universe`, `data` starts at DMX channel 1
+    pub fn encode(&mut self, universe: u16, data: &[u8]) -> Vec<u8> {
+        let sequence = self.sequences.entry(universe).or_insert(0);
+        *sequence = sequence.wrapping_add(1);
+
+        let len = E131_HEADER_LEN + data.len();
+        let flags_and_length = |from: usize| (0x7000 | (len - from) as u16).to_be_bytes();
+        let mut source_name = [0u8; 64];
+        let name = self.source_name.as_bytes();
+        let name_len = name.len().min(63);
+        source_name[..name_len].copy_from_slice(&name[..name_len]);
+
+        let mut packet = Vec::with_capacity(len);
+        // root layer
+        packet.extend_from_slice(&0x0010u16.to_be_bytes());
+        packet.extend_from_slice(&0x0000u16.to_be_bytes());
+        packet.extend_from_slice(&ACN_PACKET_IDENTIFIER);
+        packet.extend_from_slice(&flags_and_length(16));
+        packet.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet.extend_from_slice(&self.cid);
+        // framing layer
+        packet.extend_from_slice(&flags_and_length(38));
+        packet.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        packet.extend_from_slice(&source_name);
+        packet.push(self.priority.min(200));
+        packet.extend_from_slice(&0u16.to_be_bytes()); // sync address, not used
+        packet.push(*sequence);
+        packet.push(0); // options
+        packet.extend_from_slice(&universe.to_be_bytes());
+        // DMP layer
+        packet.extend_from_slice(&flags_and_length(115));
+        packet.push(VECTOR_DMP_SET_PROPERTY);
+        packet.push(0xA1); // address type & data type
+        packet.extend_from_slice(&0u16.to_be_bytes()); // first property address
+        packet.extend_from_slice(&1u16.to_be_bytes()); // address increment
+        packet.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        packet.push(0); // DMX start code
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    /// sends one frame, LED 0 first. `UnsupportedDmxMode` if the layout can't take frames
+    pub fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        for (universe, data) in self.layout.universes(colors)? {
+            let packet = self.encode(universe, &data);
+            self.socket.send_to(&packet, self.destination.for_universe(universe))?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::structures::cfg::cfg_if2::DmxMode;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(E131Destination::Multicast(E131_PORT).for_universe(258).to_string(), "239.255.1.2:5568");
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let layout = DmxLayout{ mode: DmxMode::DMX_MODE_MULTIPLE_RGB, universe: 7, address: 1, dimmer: 255 };
+        let mut sender = E131Sender::new(E131Destination::Unicast(receiver.local_addr().unwrap()), layout).unwrap();
+        sender.priority = 150;
+
+        let frame = vec![Color::rgb(9, 8, 7); 171];
+        sender.send(&frame).unwrap();
+        sender.send(&frame).unwrap();
+
+        let mut buf = [0u8; 1000];
+        let len = receiver.recv(&mut buf).unwrap();
+        // 170 LEDs, 510 of the 512 channels
+        assert_eq!(len, 126 + 510);
+        assert_eq!(&buf[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(&buf[16..18], &[0x72, 0x6C]);
+        assert_eq!(buf[108], 150);
+        assert_eq!(buf[111], 1);
+        assert_eq!(&buf[113..115], &[0, 7]);
+        assert_eq!(&buf[123..129], &[0x01, 0xFF, 0, 9, 8, 7]);
+
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(len, 126 + 3);
+        assert_eq!((buf[111], &buf[113..115]), (1, &[0u8, 8][..]));
+        receiver.recv(&mut buf).unwrap();
+        // second frame, the first universe's sequence goes on
+        assert_eq!(buf[111], 2);
+    }
+}
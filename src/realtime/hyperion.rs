@@ -0,0 +1,93 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::{pixel_bytes, resolve, PixelFormat, RealtimeSender, Transport};
+use crate::structures::color::Color;
+use crate::wled::Wled;
+
+
+/// the port WLED takes Hyperions "UDP raw" frames on
+pub const HYPERION_PORT: u16 = 19446;
+
+/// most LEDs in one UDP packet, WLED reads up to 1472 bytes
+pub const HYPERION_MAX_LEDS: usize = 490;
+
+
+/// A frame in Hyperions raw format: just `r, g, b` for every LED, no header.
+/// Fails with `InvalidValue` over `HYPERION_MAX_LEDS`, the format has no way to split a frame.
+pub fn encode_hyperion(colors: &[Color]) -> Result<Vec<u8>, WledJsonApiError> {
+    if colors.len() > HYPERION_MAX_LEDS {
+        return Err(WledJsonApiError::InvalidValue(format!("{} LEDs don't fit in one Hyperion packet", colors.len())));
+    }
+    Ok(pixel_bytes(colors, PixelFormat::Rgb))
+}
+
+
+/// Sends frames the way Hyperion (and HyperHDR) send to a "UDP raw" device.
+///
+/// Over TCP frames are written back to back with no limit on their size,
+/// so the receiver has to know how many LEDs to expect.
+#[derive(Debug)]
+pub struct HyperionSender {
+    pub transport: Transport,
+}
+
+impl HyperionSender{
+
+    pub fn new(transport: Transport) -> HyperionSender {
+        HyperionSender{ transport }
+    }
+
+    pub fn udp(target: SocketAddr) -> Result<HyperionSender, WledJsonApiError> {
+        Ok(HyperionSender::new(Transport::udp(target)?))
+    }
+
+    pub fn tcp(target: SocketAddr, connect_timeout: Duration) -> Result<HyperionSender, WledJsonApiError> {
+        Ok(HyperionSender::new(Transport::tcp(target, connect_timeout)?))
+    }
+
+    /// UDP to the same host as `wled`, port 19446 (which can't be changed on the device)
+    pub fn from_wled(wled: &Wled) -> Result<HyperionSender, WledJsonApiError> {
+        HyperionSender::udp(resolve(&wled.url, HYPERION_PORT)?)
+    }
+
+    /// sends one frame, LED 0 first
+    pub fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        let packet = if self.transport.is_datagram() {
+            encode_hyperion(colors)?
+        } else {
+            pixel_bytes(colors, PixelFormat::Rgb)
+        };
+        self.transport.send_packet(&packet)
+    }
+}
+
+impl RealtimeSender for HyperionSender {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        HyperionSender::send(self, colors)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let c = Color::rgbw(1, 2, 3, 4);
+        assert_eq!(encode_hyperion(&[c, Color::BLACK]).unwrap(), vec![1, 2, 3, 0, 0, 0]);
+        assert!(encode_hyperion(&[c; 491]).is_err());
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sender: Box<dyn RealtimeSender> = Box::new(HyperionSender::udp(receiver.local_addr().unwrap()).unwrap());
+        sender.send(&[c; 490]).unwrap();
+        assert!(sender.send(&[c; 491]).is_err());
+        let mut buf = [0u8; 2048];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 490 * 3);
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+    }
+}
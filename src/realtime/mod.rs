@@ -0,0 +1,146 @@
+//! Senders for the realtime protocols WLED receives (Settings > Sync Interfaces > Realtime).
+//! These skip the JSON API completely, they're what you want for streaming frames.
+//! `serial` does the same over a USB cable (Adalight/TPM2).
+//!
+//! Every sender takes a frame as a slice of `Color`s, LED 0 first, and implements `RealtimeSender`,
+//! so which protocol a device gets can be picked at runtime.
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::errors::WledJsonApiError;
+use crate::structures::color::Color;
+use crate::structures::info::Info;
+
+pub mod ddp;
+pub mod dmx;
+pub mod e131;
+pub mod artnet;
+pub mod udp;
+pub mod tpm2;
+pub mod hyperion;
+pub mod serial;
+
+
+/// What every realtime sender does, for when the protocol is picked per device
+/// (`Box<dyn RealtimeSender>`).
+pub trait RealtimeSender {
+    /// sends one frame, LED 0 first
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError>;
+}
+
+impl RealtimeSender for ddp::DdpSender {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        ddp::DdpSender::send(self, colors)
+    }
+}
+
+impl RealtimeSender for e131::E131Sender {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        e131::E131Sender::send(self, colors)
+    }
+}
+
+impl RealtimeSender for artnet::ArtNetSender {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        artnet::ArtNetSender::send(self, colors)
+    }
+}
+
+impl RealtimeSender for udp::UdpRealtimeSender {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        udp::UdpRealtimeSender::send(self, colors)
+    }
+}
+
+
+/// How packets get to the device, for the protocols that can go over either
+#[derive(Debug)]
+pub enum Transport {
+    Udp{ socket: UdpSocket, target: SocketAddr },
+    /// packets are written back to back, the protocol has to delimit them itself
+    Tcp(TcpStream),
+}
+
+impl Transport {
+    pub fn udp(target: SocketAddr) -> Result<Transport, WledJsonApiError> {
+        Ok(Transport::Udp{ socket: bind_for(&target)?, target })
+    }
+
+    pub fn tcp(target: SocketAddr, connect_timeout: Duration) -> Result<Transport, WledJsonApiError> {
+        let stream = TcpStream::connect_timeout(&target, connect_timeout)?;
+        stream.set_nodelay(true)?;
+        Ok(Transport::Tcp(stream))
+    }
+
+    /// true for UDP, where a packet has to fit in one datagram
+    pub fn is_datagram(&self) -> bool {
+        matches!(self, Transport::Udp{ .. })
+    }
+
+    pub fn send_packet(&mut self, packet: &[u8]) -> Result<(), WledJsonApiError> {
+        match self {
+            Transport::Udp{ socket, target } => { socket.send_to(packet, *target)?; }
+            Transport::Tcp(stream) => stream.write_all(packet)?,
+        }
+        Ok(())
+    }
+}
+
+
+/// How many channels each LED takes on the wire
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    #[default]
+    Rgb,
+    Rgbw,
+}
+
+impl PixelFormat {
+    pub fn channels(&self) -> usize {
+        match self {
+            PixelFormat::Rgb => 3,
+            PixelFormat::Rgbw => 4,
+        }
+    }
+
+    /// RGBW if any segment has a white channel (`info.leds.lc` bit 1 (0x02), or the old `info.leds.rgbw`)
+    pub fn from_info(info: &Info) -> PixelFormat {
+        let rgbw = info.leds.as_ref()
+            .map(|l| l.lc.is_some_and(|lc| lc & 0x02 != 0) || l.rgbw == Some(true))
+            .unwrap_or(false);
+        if rgbw { PixelFormat::Rgbw } else { PixelFormat::Rgb }
+    }
+}
+
+
+/// `colors` as raw channel bytes. a missing white channel is sent as 0, an extra one is dropped
+pub(crate) fn pixel_bytes(colors: &[Color], format: PixelFormat) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(colors.len() * format.channels());
+    for c in colors {
+        bytes.extend_from_slice(&[c.r, c.g, c.b]);
+        if format == PixelFormat::Rgbw {
+            bytes.push(c.w.unwrap_or(0));
+        }
+    }
+    bytes
+}
+
+/// the host of a `Wled` url with the port of a realtime protocol
+pub(crate) fn resolve(url: &Url, port: u16) -> Result<SocketAddr, WledJsonApiError> {
+    let host = url.host_str().ok_or_else(||{WledJsonApiError::InvalidUrl(url.to_string())})?;
+    // IPv6 hosts come with brackets in urls, but not in socket addresses
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    (host, port).to_socket_addrs()?
+        .next()
+        .ok_or_else(||{WledJsonApiError::InvalidUrl(url.to_string())})
+}
+
+/// a socket on any local port, same address family as `target`
+pub(crate) fn bind_for(target: &SocketAddr) -> Result<UdpSocket, WledJsonApiError> {
+    let local = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    Ok(UdpSocket::bind(local)?)
+}
@@ -0,0 +1,117 @@
+use std::io::Write;
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::tpm2::{TPM2_BLOCK_END, TPM2_DATA_FRAME};
+use crate::realtime::{pixel_bytes, PixelFormat, RealtimeSender};
+use crate::structures::cfg::Cfg;
+use crate::structures::color::Color;
+
+
+/// WLEDs default serial speed
+pub const DEFAULT_BAUD: u32 = 115200;
+
+const TPM2_BLOCK_START: u8 = 0xC9;
+
+
+/// The two framings WLED reads off its serial port ("USB Adalight/TPM2" in `info.lm`).
+/// Both are RGB only.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialProtocol {
+    /// `Ada`, LED count - 1 (high, low), `high ^ low ^ 0x55`, RGB data
+    #[default]
+    Adalight,
+    /// `0xC9 0xDA`, data size (high, low), RGB data, `0x36`
+    Tpm2,
+}
+
+
+/// A frame in Adalight framing. Adalight can't say "no LEDs", so an empty frame is an `InvalidValue`,
+/// and neither can it go over 65536.
+pub fn encode_adalight(colors: &[Color]) -> Result<Vec<u8>, WledJsonApiError> {
+    if colors.is_empty() || colors.len() > 65536 {
+        return Err(WledJsonApiError::InvalidValue(format!("Adalight can't send {} LEDs", colors.len())));
+    }
+    let [hi, lo] = ((colors.len() - 1) as u16).to_be_bytes();
+    Ok([&[b'A', b'd', b'a', hi, lo, hi ^ lo ^ 0x55][..], &pixel_bytes(colors, PixelFormat::Rgb)].concat())
+}
+
+/// A frame in TPM2 framing, up to 21845 LEDs (65535 bytes), `InvalidValue` over that.
+pub fn encode_tpm2(colors: &[Color]) -> Result<Vec<u8>, WledJsonApiError> {
+    let data = pixel_bytes(colors, PixelFormat::Rgb);
+    let size = u16::try_from(data.len())
+        .map_err(|_| WledJsonApiError::InvalidValue(format!("TPM2 can't send {} LEDs", colors.len())))?;
+    let [hi, lo] = size.to_be_bytes();
+    Ok([&[TPM2_BLOCK_START, TPM2_DATA_FRAME, hi, lo][..], &data, &[TPM2_BLOCK_END]].concat())
+}
+
+/// the serial speed the device is set to (`cfg.hw.baud` is in 100s), `DEFAULT_BAUD` if it isn't set
+pub fn baud_from_cfg(cfg: &Cfg) -> u32 {
+    cfg.hw.as_ref()
+        .and_then(|h| h.baud)
+        .filter(|b| *b != 0)
+        .map(|b| b as u32 * 100)
+        .unwrap_or(DEFAULT_BAUD)
+}
+
+
+/// Writes frames to anything `Write`, meant for a serial port opened with whatever crate you like
+/// (at `baud_from_cfg`, 8N1).
+#[derive(Debug)]
+pub struct SerialEncoder<W: Write> {
+    pub writer: W,
+    pub protocol: SerialProtocol,
+}
+
+impl<W: Write> SerialEncoder<W>{
+
+    pub fn new(writer: W, protocol: SerialProtocol) -> SerialEncoder<W> {
+        SerialEncoder{ writer, protocol }
+    }
+
+    /// writes one frame, LED 0 first, and flushes
+    pub fn write_frame(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        let frame = match self.protocol {
+            SerialProtocol::Adalight => encode_adalight(colors)?,
+            SerialProtocol::Tpm2 => encode_tpm2(colors)?,
+        };
+        self.writer.write_all(&frame)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RealtimeSender for SerialEncoder<W> {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        self.write_frame(colors)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let c = Color::rgbw(1, 2, 3, 4);
+        assert_eq!(encode_adalight(&[c]).unwrap(), vec![b'A', b'd', b'a', 0, 0, 0x55, 1, 2, 3]);
+        assert_eq!(&encode_adalight(&[c; 300]).unwrap()[3..6], &[0x01, 0x2B, 0x01 ^ 0x2B ^ 0x55]);
+        assert!(encode_adalight(&[]).is_err());
+        assert_eq!(encode_tpm2(&[c]).unwrap(), vec![0xC9, 0xDA, 0, 3, 1, 2, 3, 0x36]);
+        assert!(encode_tpm2(&[c; 21846]).is_err());
+
+        let mut encoder = SerialEncoder::new(Vec::new(), SerialProtocol::Tpm2);
+        encoder.write_frame(&[c, c]).unwrap();
+        encoder.protocol = SerialProtocol::Adalight;
+        encoder.write_frame(&[c]).unwrap();
+        let written = encoder.into_inner();
+        assert_eq!(written.len(), 11 + 9);
+        assert_eq!(&written[11..14], b"Ada");
+
+        assert_eq!(baud_from_cfg(&Cfg::default()), DEFAULT_BAUD);
+    }
+}
@@ -0,0 +1,131 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::{pixel_bytes, resolve, PixelFormat, RealtimeSender, Transport};
+use crate::structures::color::Color;
+use crate::wled::Wled;
+
+
+/// TPM2.net default port, the same as the notifiers supplemental port (`Sync::port1`)
+pub const TPM2NET_PORT: u16 = 65506;
+
+/// most data bytes per packet WLED takes (it reads up to 1472 bytes, minus the 7 of framing), 488 LEDs
+pub const TPM2NET_MAX_DATA: usize = 1464;
+
+pub(crate) const TPM2NET_BLOCK_START: u8 = 0x9C;
+pub(crate) const TPM2_DATA_FRAME: u8 = 0xDA;
+pub(crate) const TPM2_BLOCK_END: u8 = 0x36;
+
+
+/// A frame as TPM2.net packets: `0x9C 0xDA`, data size, packet number (from 1), packet count, RGB data, `0x36`.
+///
+/// WLED works out where a packet goes from its own size and number, so every packet has the same size;
+/// the last one is padded with black (LEDs past the end of the strip are ignored).
+pub fn encode_tpm2net(colors: &[Color]) -> Vec<Vec<u8>> {
+    let data = pixel_bytes(colors, PixelFormat::Rgb);
+    let packets = data.len().div_ceil(TPM2NET_MAX_DATA).max(1);
+    // as even a split as whole LEDs allow
+    let per_packet = data.len().div_ceil(packets).div_ceil(3) * 3;
+
+    (0..packets).map(|n| {
+        let mut chunk = data.get(n * per_packet..).unwrap_or(&[]).iter().take(per_packet).copied().collect::<Vec<u8>>();
+        chunk.resize(per_packet, 0);
+        let mut packet = Vec::with_capacity(per_packet + 7);
+        packet.push(TPM2NET_BLOCK_START);
+        packet.push(TPM2_DATA_FRAME);
+        packet.extend_from_slice(&(per_packet as u16).to_be_bytes());
+        packet.push(n as u8 + 1);
+        packet.push(packets as u8);
+        packet.extend_from_slice(&chunk);
+        packet.push(TPM2_BLOCK_END);
+        packet
+    }).collect()
+}
+
+
+/// Sends frames over TPM2.net, UDP (what WLED takes) or TCP
+#[derive(Debug)]
+pub struct Tpm2NetSender {
+    pub transport: Transport,
+}
+
+impl Tpm2NetSender{
+
+    pub fn new(transport: Transport) -> Tpm2NetSender {
+        Tpm2NetSender{ transport }
+    }
+
+    pub fn udp(target: SocketAddr) -> Result<Tpm2NetSender, WledJsonApiError> {
+        Ok(Tpm2NetSender::new(Transport::udp(target)?))
+    }
+
+    pub fn tcp(target: SocketAddr, connect_timeout: Duration) -> Result<Tpm2NetSender, WledJsonApiError> {
+        Ok(Tpm2NetSender::new(Transport::tcp(target, connect_timeout)?))
+    }
+
+    /// UDP to the same host as `wled`, on `cfg.if.sync.port1` if `wled.cfg` was fetched, 65506 otherwise
+    pub fn from_wled(wled: &Wled) -> Result<Tpm2NetSender, WledJsonApiError> {
+        let port = wled.cfg.as_ref()
+            .and_then(|c| c.if_field.as_ref())
+            .and_then(|i| i.sync.as_ref())
+            .and_then(|s| s.port1)
+            .filter(|p| *p != 0)
+            .unwrap_or(TPM2NET_PORT);
+        Tpm2NetSender::udp(resolve(&wled.url, port)?)
+    }
+
+    /// sends one frame, LED 0 first
+    pub fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        for packet in encode_tpm2net(colors) {
+            self.transport.send_packet(&packet)?;
+        }
+        Ok(())
+    }
+}
+
+impl RealtimeSender for Tpm2NetSender {
+    fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        Tpm2NetSender::send(self, colors)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::{TcpListener, UdpSocket};
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(encode_tpm2net(&[Color::rgb(1, 2, 3)]), vec![vec![0x9C, 0xDA, 0, 3, 1, 1, 1, 2, 3, 0x36]]);
+
+        // 500 LEDs don't fit in one packet, so two of 250
+        let packets = encode_tpm2net(&[Color::rgb(9, 9, 9); 500]);
+        assert_eq!(packets.len(), 2);
+        assert!(packets.iter().all(|p| p.len() == 750 + 7));
+        assert_eq!(&packets[1][..6], &[0x9C, 0xDA, 0x02, 0xEE, 2, 2]);
+
+        // uneven split, the last one gets padded
+        let packets = encode_tpm2net(&[Color::rgb(9, 9, 9); 977]);
+        assert_eq!(packets.len(), 3);
+        assert!(packets.iter().all(|p| p.len() == 326 * 3 + 7));
+        assert_eq!(&packets[2][packets[2].len() - 4..], &[0, 0, 0, 0x36]);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sender = Tpm2NetSender::udp(receiver.local_addr().unwrap()).unwrap();
+        sender.send(&[Color::rgb(1, 2, 3)]).unwrap();
+        let mut buf = [0u8; 64];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 10);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut sender = Tpm2NetSender::tcp(listener.local_addr().unwrap(), Duration::from_secs(1)).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        sender.send(&[Color::rgb(1, 2, 3)]).unwrap();
+        let mut buf = [0u8; 10];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x9C, 0xDA, 0, 3, 1, 1, 1, 2, 3, 0x36]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::errors::WledJsonApiError;
+use crate::realtime::{bind_for, pixel_bytes, resolve, PixelFormat};
+use crate::structures::color::Color;
+use crate::wled::Wled;
+
+
+/// the notifier port (`info.udpport`), WLED takes the realtime protocols on it too
+pub const UDP_REALTIME_PORT: u16 = 21324;
+
+
+/// WLEDs own UDP realtime protocols, the first byte of every packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpProtocol {
+    /// `[1, timeout, (index, r, g, b)...]`; any LEDs out of the first 256, in any order
+    Warls = 1,
+    /// `[2, timeout, r, g, b...]`; LEDs 0 to 489
+    Drgb = 2,
+    /// `[3, timeout, r, g, b, w...]`; LEDs 0 to 366
+    Drgbw = 3,
+    /// `[4, timeout, start high, start low, r, g, b...]`; 489 LEDs from `start`, as many packets as it takes
+    Dnrgb = 4,
+}
+
+impl UdpProtocol {
+    /// most LEDs that fit in one packet
+    pub fn max_leds(&self) -> usize {
+        match self {
+            UdpProtocol::Warls => 256,
+            UdpProtocol::Drgb => 490,
+            UdpProtocol::Drgbw => 367,
+            UdpProtocol::Dnrgb => 489,
+        }
+    }
+
+    /// DRGBW if the LEDs have a white channel and fit in one packet, DRGB if they fit, DNRGB otherwise
+    /// (which has no white channel)
+    pub fn auto(led_count: usize, format: PixelFormat) -> UdpProtocol {
+        match format {
+            PixelFormat::Rgbw if led_count <= UdpProtocol::Drgbw.max_leds() => UdpProtocol::Drgbw,
+            _ if led_count <= UdpProtocol::Drgb.max_leds() => UdpProtocol::Drgb,
+            _ => UdpProtocol::Dnrgb,
+        }
+    }
+}
+
+
+/// the second byte of every packet, what WLED does once packets stop coming
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpTimeout {
+    /// 1 to 254 seconds, then back to whatever it was doing
+    Seconds(u8),
+    /// stay in realtime mode until told otherwise
+    Forever,
+    /// leave realtime mode right away, the LED data in the packet is ignored
+    Exit,
+}
+
+impl UdpTimeout {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            UdpTimeout::Seconds(s) => (*s).clamp(1, 254),
+            UdpTimeout::Forever => 255,
+            UdpTimeout::Exit => 0,
+        }
+    }
+
+    /// `cfg.if.live.timeout` is in 100 ms steps, the packet only does whole seconds so it's rounded up
+    pub fn from_live_timeout(timeout: u16) -> UdpTimeout {
+        UdpTimeout::Seconds(timeout.div_ceil(10).clamp(1, 254) as u8)
+    }
+}
+
+impl Default for UdpTimeout {
+    /// WLEDs own default realtime timeout is 2.5 s
+    fn default() -> Self {
+        UdpTimeout::Seconds(3)
+    }
+}
+
+
+/// `colors` as WARLS, at `indices` (each LED once, index 0 to 255)
+pub fn encode_warls(timeout: UdpTimeout, leds: &[(u8, Color)]) -> Vec<u8> {
+    let mut packet = vec![UdpProtocol::Warls as u8, timeout.to_byte()];
+    for (i, c) in leds {
+        packet.extend_from_slice(&[*i, c.r, c.g, c.b]);
+    }
+    packet
+}
+
+/// A frame (LED 0 first) as packets of `protocol`.
+/// Only DNRGB splits frames, the rest fail with `InvalidValue` if the frame doesn't fit in one packet.
+pub fn encode(protocol: UdpProtocol, timeout: UdpTimeout, colors: &[Color]) -> Result<Vec<Vec<u8>>, WledJsonApiError> {
+    if protocol != UdpProtocol::Dnrgb && colors.len() > protocol.max_leds() {
+        return Err(WledJsonApiError::InvalidValue(format!("{} LEDs don't fit in one {protocol:?} packet", colors.len())));
+    }
+    let header = [protocol as u8, timeout.to_byte()];
+    Ok(match protocol {
+        UdpProtocol::Warls => {
+            let indexed: Vec<(u8, Color)> = colors.iter().enumerate().map(|(i, c)| (i as u8, *c)).collect();
+            vec![encode_warls(timeout, &indexed)]
+        }
+        UdpProtocol::Drgb | UdpProtocol::Drgbw => {
+            let format = if protocol == UdpProtocol::Drgbw { PixelFormat::Rgbw } else { PixelFormat::Rgb };
+            vec![[&header[..], &pixel_bytes(colors, format)].concat()]
+        }
+        UdpProtocol::Dnrgb => {
+            let chunks: Vec<&[Color]> = if colors.is_empty() { vec![&[]] } else { colors.chunks(protocol.max_leds()).collect() };
+            chunks.into_iter().enumerate().map(|(n, chunk)| {
+                let start = (n * protocol.max_leds()) as u16;
+                [&header[..], &start.to_be_bytes(), &pixel_bytes(chunk, PixelFormat::Rgb)].concat()
+            }).collect()
+        }
+    })
+}
+
+
+/// Sends frames with one of WLEDs UDP realtime protocols
+#[derive(Debug)]
+pub struct UdpRealtimeSender {
+    pub socket: UdpSocket,
+    pub target: SocketAddr,
+    pub protocol: UdpProtocol,
+    pub timeout: UdpTimeout,
+}
+
+impl UdpRealtimeSender{
+
+    pub fn new(target: SocketAddr, protocol: UdpProtocol, timeout: UdpTimeout) -> Result<UdpRealtimeSender, WledJsonApiError> {
+        Ok(UdpRealtimeSender{
+            socket: bind_for(&target)?,
+            target,
+            protocol,
+            timeout,
+        })
+    }
+
+    /// Picks the protocol for the devices LEDs (`UdpProtocol::auto`), so `wled.info` has to be fetched first
+    /// (`MissingKey` if not). The port is `info.udpport`.
+    /// If `wled.cfg` is there too, the timeout follows the devices realtime timeout (`cfg.if.live.timeout`).
+    pub fn from_wled(wled: &Wled) -> Result<UdpRealtimeSender, WledJsonApiError> {
+        let info = wled.info.as_ref().ok_or(WledJsonApiError::MissingKey)?;
+        let count = info.leds.as_ref().and_then(|l| l.count).ok_or(WledJsonApiError::MissingKey)?;
+        let protocol = UdpProtocol::auto(count as usize, PixelFormat::from_info(info));
+        let timeout = wled.cfg.as_ref()
+            .and_then(|c| c.if_field.as_ref())
+            .and_then(|i| i.live.as_ref())
+            .and_then(|l| l.timeout)
+            .map(UdpTimeout::from_live_timeout)
+            .unwrap_or_default();
+        UdpRealtimeSender::new(resolve(&wled.url, info.udpport.unwrap_or(UDP_REALTIME_PORT))?, protocol, timeout)
+    }
+
+    /// sends one frame, LED 0 first
+    pub fn send(&mut self, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        for packet in encode(self.protocol, self.timeout, colors)? {
+            self.socket.send_to(&packet, self.target)?;
+        }
+        Ok(())
+    }
+
+    /// sends only some LEDs (WARLS), the rest keep what they had
+    pub fn send_leds(&mut self, leds: &[(u8, Color)]) -> Result<(), WledJsonApiError> {
+        self.socket.send_to(&encode_warls(self.timeout, leds), self.target)?;
+        Ok(())
+    }
+
+    /// tells WLED to leave realtime mode now instead of waiting for the timeout
+    pub fn exit(&mut self) -> Result<(), WledJsonApiError> {
+        self.socket.send_to(&[UdpProtocol::Drgb as u8, UdpTimeout::Exit.to_byte()], self.target)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(UdpProtocol::auto(300, PixelFormat::Rgbw), UdpProtocol::Drgbw);
+        assert_eq!(UdpProtocol::auto(400, PixelFormat::Rgbw), UdpProtocol::Drgb);
+        assert_eq!(UdpProtocol::auto(600, PixelFormat::Rgb), UdpProtocol::Dnrgb);
+        assert_eq!(UdpTimeout::from_live_timeout(25).to_byte(), 3);
+
+        let c = Color::rgbw(1, 2, 3, 4);
+        assert_eq!(encode(UdpProtocol::Warls, UdpTimeout::Forever, &[c]).unwrap(), vec![vec![1, 255, 0, 1, 2, 3]]);
+        assert_eq!(encode(UdpProtocol::Drgbw, UdpTimeout::Seconds(2), &[c]).unwrap(), vec![vec![3, 2, 1, 2, 3, 4]]);
+        assert!(encode(UdpProtocol::Drgb, UdpTimeout::Forever, &[c; 491]).is_err());
+
+        let packets = encode(UdpProtocol::Dnrgb, UdpTimeout::Forever, &[c; 500]).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].len(), 4 + 489 * 3);
+        assert_eq!(&packets[1][..7], &[4, 255, 0x01, 0xE9, 1, 2, 3]);
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut sender = UdpRealtimeSender::new(receiver.local_addr().unwrap(), UdpProtocol::Drgb, UdpTimeout::Seconds(1)).unwrap();
+        sender.send(&[c]).unwrap();
+        sender.exit().unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], &[2, 1, 1, 2, 3]);
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 2);
+    }
+}
@@ -162,7 +162,7 @@ pub struct Live {
     #[serde(default = "none_function")]
     pub port: Option<u16>,
 
-    /// multicast or unicast; who the fuck knows which one is which
+    /// true for multicast, false for unicast (E1.31)
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
     pub mc: Option<bool>,
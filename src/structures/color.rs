@@ -0,0 +1,286 @@
+use std::fmt;
+use std::str::FromStr;
+use serde;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::ser::SerializeSeq;
+use crate::errors::WledJsonApiError;
+
+
+/// An RGB or RGBW color, the way WLED takes them in `col` (and everywhere else colors show up).
+///
+/// Serializes to `[r, g, b]` or `[r, g, b, w]`.
+/// Deserializes from those, from hex strings ("FF8000" or "20FF8000", white first like WLED does it),
+/// and from a plain number, which WLED treats as a color temperature in Kelvin.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    /// white channel, None for RGB only
+    pub w: Option<u8>,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color{ r, g, b, w: None }
+    }
+
+    pub const fn rgbw(r: u8, g: u8, b: u8, w: u8) -> Color {
+        Color{ r, g, b, w: Some(w) }
+    }
+
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+
+    pub fn is_rgbw(&self) -> bool {
+        self.w.is_some()
+    }
+
+    /// hue in degrees (0 to 360), saturation and value from 0 to 1
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Color {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let s = saturation.clamp(0.0, 1.0);
+        let v = value.clamp(0.0, 1.0);
+        let c = v * s;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        let to_u8 = |f: f32| ((f + m) * 255.0).round() as u8;
+        Color::rgb(to_u8(r), to_u8(g), to_u8(b))
+    }
+
+    /// Color temperature to RGB, same approximation WLED uses (colorKtoRGB in colors.cpp)
+    /// so the result matches what WLED would show for that temperature.
+    pub fn from_kelvin(kelvin: u16) -> Color {
+        let temp = kelvin as f32 / 100.0;
+        let (r, g, b) = if temp <= 66.0 {
+            let g = 99.470_8 * temp.ln() - 161.119_57;
+            let b = if temp <= 19.0 { 0.0 } else { 138.517_73 * (temp - 10.0).ln() - 305.044_8 };
+            (255.0, g, b)
+        } else {
+            (
+                329.698_73 * (temp - 60.0).powf(-0.133_204_76),
+                288.122_16 * (temp - 60.0).powf(-0.075_514_85),
+                255.0,
+            )
+        };
+        let to_u8 = |f: f32| f.round().clamp(0.0, 255.0) as u8;
+        Color::rgb(to_u8(r), to_u8(g), to_u8(b))
+    }
+
+    /// "RRGGBB", or "WWRRGGBB" if there's a white channel
+    pub fn to_hex(&self) -> String {
+        match self.w {
+            Some(w) => format!("{w:02X}{:02X}{:02X}{:02X}", self.r, self.g, self.b),
+            None => format!("{:02X}{:02X}{:02X}", self.r, self.g, self.b),
+        }
+    }
+}
+
+impl From<[u8; 3]> for Color {
+    fn from(c: [u8; 3]) -> Self {
+        Color::rgb(c[0], c[1], c[2])
+    }
+}
+
+impl From<[u8; 4]> for Color {
+    fn from(c: [u8; 4]) -> Self {
+        Color::rgbw(c[0], c[1], c[2], c[3])
+    }
+}
+
+impl TryFrom<&[u8]> for Color {
+    type Error = WledJsonApiError;
+    fn try_from(c: &[u8]) -> Result<Color, WledJsonApiError> {
+        match c {
+            [r, g, b] => Ok(Color::rgb(*r, *g, *b)),
+            [r, g, b, w] => Ok(Color::rgbw(*r, *g, *b, *w)),
+            _ => Err(WledJsonApiError::InvalidColor(format!("{c:?} has {} channels, needs 3 or 4", c.len()))),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = WledJsonApiError;
+    /// "FF8000", "#FF8000" or "20FF8000" (white first)
+    fn from_str(s: &str) -> Result<Color, WledJsonApiError> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let invalid = || WledJsonApiError::InvalidColor(s.to_string());
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let v = u32::from_str_radix(hex, 16).map_err(|_| invalid())?;
+        let [w, r, g, b] = v.to_be_bytes();
+        match hex.len() {
+            6 => Ok(Color::rgb(r, g, b)),
+            8 => Ok(Color::rgbw(r, g, b, w)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.w {
+            Some(w) => [self.r, self.g, self.b, w].serialize(serializer),
+            None => [self.r, self.g, self.b].serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        Slot::deserialize(deserializer)?
+            .into_color()
+            .map_err(serde::de::Error::custom)?
+            .ok_or_else(|| serde::de::Error::custom("empty color"))
+    }
+}
+
+
+/// every way WLED writes one color
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Slot {
+    Channels(Vec<u8>),
+    Hex(String),
+    Kelvin(u16),
+}
+
+impl Slot {
+    /// None for `[]`, which WLED reads as "leave this slot alone"
+    fn into_color(self) -> Result<Option<Color>, WledJsonApiError> {
+        match self {
+            Slot::Channels(c) if c.is_empty() => Ok(None),
+            Slot::Channels(c) => Color::try_from(&*c).map(Some),
+            Slot::Hex(h) => h.parse().map(Some),
+            Slot::Kelvin(0) => Ok(Some(Color::BLACK)),
+            Slot::Kelvin(k) => Ok(Some(Color::from_kelvin(k))),
+        }
+    }
+}
+
+
+/// how `SegColors` gets written when sending
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorFormat {
+    /// `[[255,128,0],[0,0,0]]`
+    #[default]
+    Array,
+    /// `["FF8000","000000"]`
+    Hex,
+}
+
+
+/// The three color slots of a segment, what goes in `Seg::col`.
+///
+/// A None slot is left alone by WLED (it's sent as `[]`), trailing None slots aren't sent at all.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegColors {
+    /// primary color, the one most effects use
+    pub primary: Option<Color>,
+
+    /// secondary (background) color
+    pub secondary: Option<Color>,
+
+    /// tertiary color
+    pub tertiary: Option<Color>,
+
+    /// only matters for sending, doesn't change what WLED does
+    pub format: ColorFormat,
+}
+
+impl SegColors {
+    pub fn from_primary(primary: Color) -> SegColors {
+        SegColors{ primary: Some(primary), ..Default::default() }
+    }
+
+    pub fn new(primary: Option<Color>, secondary: Option<Color>, tertiary: Option<Color>) -> SegColors {
+        SegColors{ primary, secondary, tertiary, format: ColorFormat::Array }
+    }
+
+    /// send as hex strings instead of arrays
+    pub fn hex(mut self) -> SegColors {
+        self.format = ColorFormat::Hex;
+        self
+    }
+
+    pub fn slots(&self) -> [Option<Color>; 3] {
+        [self.primary, self.secondary, self.tertiary]
+    }
+}
+
+impl Serialize for SegColors {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let slots = self.slots();
+        let len = slots.iter().rposition(|s| s.is_some()).map_or(0, |i| i + 1);
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        for slot in &slots[..len] {
+            match (slot, self.format) {
+                (None, _) => seq.serialize_element(&[0u8; 0])?,
+                (Some(c), ColorFormat::Array) => seq.serialize_element(c)?,
+                (Some(c), ColorFormat::Hex) => seq.serialize_element(&c.to_hex())?,
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for SegColors {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<SegColors, D::Error> {
+        let slots: Vec<Slot> = Vec::deserialize(deserializer)?;
+        if slots.len() > 3 {
+            return Err(serde::de::Error::custom(format!("{} color slots, a segment only has 3", slots.len())));
+        }
+        let format = match slots.first() {
+            Some(Slot::Hex(_)) => ColorFormat::Hex,
+            _ => ColorFormat::Array,
+        };
+        let mut colors = [None; 3];
+        for (i, slot) in slots.into_iter().enumerate() {
+            colors[i] = slot.into_color().map_err(serde::de::Error::custom)?;
+        }
+        Ok(SegColors{ primary: colors[0], secondary: colors[1], tertiary: colors[2], format })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!("FF8000".parse::<Color>().unwrap(), Color::rgb(255, 128, 0));
+        assert_eq!("#20ff8000".parse::<Color>().unwrap(), Color::rgbw(255, 128, 0, 32));
+        assert!("FF80".parse::<Color>().is_err());
+        assert_eq!(Color::rgbw(255, 128, 0, 32).to_hex(), "20FF8000");
+        assert_eq!(Color::from_hsv(120.0, 1.0, 1.0), Color::rgb(0, 255, 0));
+        let warm = Color::from_kelvin(2700);
+        assert!(warm.r == 255 && warm.g > warm.b);
+        assert_eq!(Color::from_kelvin(10000).b, 255);
+
+        let c: SegColors = serde_json::from_str(r#"[[255,160,0],"0000FF",[]]"#).unwrap();
+        assert_eq!(c.primary, Some(Color::rgb(255, 160, 0)));
+        assert_eq!(c.secondary, Some(Color::rgb(0, 0, 255)));
+        assert_eq!(c.tertiary, None);
+        assert!(serde_json::from_str::<SegColors>("[[1,2]]").is_err());
+        assert!(serde_json::from_str::<SegColors>("[[],[],[],[]]").is_err());
+
+        let c = SegColors::new(None, Some(Color::rgbw(1, 2, 3, 4)), None);
+        assert_eq!(serde_json::to_string(&c).unwrap(), "[[],[1,2,3,4]]");
+        assert_eq!(serde_json::to_string(&c.hex()).unwrap(), r#"[[],"04010203"]"#);
+    }
+}
@@ -0,0 +1,25 @@
+use serde;
+use serde::{Serialize, Deserialize};
+use crate::errors::WledJsonApiError;
+use crate::structures::state::State;
+use crate::structures::info::Info;
+use crate::structures::effects::Effects;
+use crate::structures::palettes::Palettes;
+
+
+/// Everything WLED sends from plain `/json`, state + info + effect names + palette names in one go
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Full {
+    pub state: State,
+    pub info: Info,
+    pub effects: Effects,
+    pub palettes: Palettes,
+}
+
+impl TryFrom<&str> for Full{
+    type Error = WledJsonApiError;
+    fn try_from(str_in: &str) -> Result<Full, WledJsonApiError> {
+        serde_json::from_str(str_in).map_err(|e| {WledJsonApiError::SerdeError(e)})
+    }
+}
@@ -0,0 +1,164 @@
+use serde;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::ser::SerializeSeq;
+use crate::errors::WledJsonApiError;
+use crate::structures::color::{Color, ColorFormat};
+
+
+/// roughly what fits in WLEDs JSON buffer on an ESP8266 (the smaller one) with some room to spare
+pub const DEFAULT_LEDS_PER_REQUEST: usize = 256;
+
+
+/// One entry of the segment `i` array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedEntry {
+    /// the LED after the last one that was set (LED 0 of the segment if nothing was set yet)
+    Next(Color),
+    /// one LED, index relative to the segment start. following `Next`s continue from here
+    At(u16, Color),
+    /// LEDs start to stop (stop not included), relative to the segment start
+    Range(u16, u16, Color),
+}
+
+
+/// The segment `i` array, per LED colors.
+/// Setting this freezes whatever effect the segment was running.
+///
+/// On the wire it's one flat array: a color on its own is `Next`, one number before a color is `At`,
+/// two numbers before a color is `Range`.
+/// `[[255,0,0], 5, [0,255,0], 10, 20, "0000FF"]` is LED 0 red, LED 5 green and LEDs 10 to 19 blue.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct IndividualLeds {
+    pub entries: Vec<LedEntry>,
+
+    /// hex strings are about half the size of arrays, which matters a lot for long frames
+    pub format: ColorFormat,
+}
+
+impl IndividualLeds {
+    pub fn new(entries: Vec<LedEntry>) -> IndividualLeds {
+        IndividualLeds{ entries, format: ColorFormat::Array }
+    }
+
+    /// send as hex strings instead of arrays
+    pub fn hex(mut self) -> IndividualLeds {
+        self.format = ColorFormat::Hex;
+        self
+    }
+
+    /// `colors` in order, starting at LED `start` of the segment
+    pub fn frame(start: u16, colors: &[Color]) -> IndividualLeds {
+        let mut entries = Vec::with_capacity(colors.len());
+        for (n, c) in colors.iter().enumerate() {
+            entries.push(if n == 0 { LedEntry::At(start, *c) } else { LedEntry::Next(*c) });
+        }
+        IndividualLeds::new(entries)
+    }
+
+    /// Splits a whole frame into pieces of at most `per_request` LEDs, each of them its own
+    /// `frame` starting where the last one stopped, so they can be sent one request at a time.
+    /// A `per_request` of 0 is treated as 1.
+    /// `InvalidValue` if the frame runs past LED 65535, the last one WLED can address.
+    pub fn chunked(start: u16, colors: &[Color], per_request: usize) -> Result<Vec<IndividualLeds>, WledJsonApiError> {
+        let per_request = per_request.max(1);
+        colors.chunks(per_request)
+            .enumerate()
+            .map(|(n, chunk)| {
+                let at = u16::try_from(n * per_request).ok()
+                    .and_then(|offset| start.checked_add(offset))
+                    .filter(|at| chunk.len() <= (u16::MAX - at) as usize + 1)
+                    .ok_or_else(|| WledJsonApiError::InvalidValue(format!("{} LEDs from LED {start} go past LED 65535", colors.len())))?;
+                Ok(IndividualLeds::frame(at, chunk))
+            })
+            .collect()
+    }
+}
+
+impl Serialize for IndividualLeds {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for entry in &self.entries {
+            let color = match entry {
+                LedEntry::Next(c) => c,
+                LedEntry::At(i, c) => {
+                    seq.serialize_element(i)?;
+                    c
+                }
+                LedEntry::Range(start, stop, c) => {
+                    seq.serialize_element(start)?;
+                    seq.serialize_element(stop)?;
+                    c
+                }
+            };
+            match self.format {
+                ColorFormat::Array => seq.serialize_element(color)?,
+                ColorFormat::Hex => seq.serialize_element(&color.to_hex())?,
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for IndividualLeds {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<IndividualLeds, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Item {
+            Index(u16),
+            Color(Color),
+        }
+        let items: Vec<Item> = Vec::deserialize(deserializer)?;
+        let mut leds = IndividualLeds::default();
+        let mut indices: Vec<u16> = Vec::new();
+        for item in items {
+            match item {
+                Item::Index(i) => indices.push(i),
+                Item::Color(c) => {
+                    leds.entries.push(match indices[..] {
+                        [] => LedEntry::Next(c),
+                        [i] => LedEntry::At(i, c),
+                        [start, stop] => LedEntry::Range(start, stop, c),
+                        _ => return Err(serde::de::Error::custom(format!("{} indices in a row in the i array", indices.len()))),
+                    });
+                    indices.clear();
+                }
+            }
+        }
+        if !indices.is_empty() {
+            return Err(serde::de::Error::custom("i array ends with an index and no color"));
+        }
+        Ok(leds)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let leds: IndividualLeds = serde_json::from_str(r#"[[255,0,0],5,[0,255,0],10,20,"0000FF"]"#).unwrap();
+        assert_eq!(leds.entries, vec![
+            LedEntry::Next(Color::rgb(255, 0, 0)),
+            LedEntry::At(5, Color::rgb(0, 255, 0)),
+            LedEntry::Range(10, 20, Color::rgb(0, 0, 255)),
+        ]);
+        assert_eq!(serde_json::to_string(&leds).unwrap(), "[[255,0,0],5,[0,255,0],10,20,[0,0,255]]");
+        assert_eq!(serde_json::to_string(&leds.hex()).unwrap(), r#"["FF0000",5,"00FF00",10,20,"0000FF"]"#);
+        assert!(serde_json::from_str::<IndividualLeds>("[1,2,3,[0,0,0]]").is_err());
+        assert!(serde_json::from_str::<IndividualLeds>("[[0,0,0],4]").is_err());
+
+        let frame = vec![Color::rgb(1, 1, 1); 5];
+        let chunks = IndividualLeds::chunked(10, &frame, 2).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(serde_json::to_string(&chunks[1].clone().hex()).unwrap(), r#"[12,"010101","010101"]"#);
+        assert_eq!(chunks[2].entries, vec![LedEntry::At(14, Color::rgb(1, 1, 1))]);
+
+        // LED 65535 is the last one there is
+        let red = Color::rgb(255, 0, 0);
+        assert!(matches!(IndividualLeds::chunked(65000, &[red; 1000], 100), Err(WledJsonApiError::InvalidValue(_))));
+        assert!(matches!(IndividualLeds::chunked(65000, &[red; 1000], 1000), Err(WledJsonApiError::InvalidValue(_))));
+        assert_eq!(IndividualLeds::chunked(65500, &[red; 36], 10).unwrap().len(), 4);
+    }
+}
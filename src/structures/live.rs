@@ -1,23 +1,32 @@
 use serde;
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Serializer};
 use crate::errors::WledJsonApiError;
+use crate::structures::color::Color;
+use crate::structures::info::Info;
 use crate::structures::none_function;
 
 
 
-/// Not Done, broken, dont use
+/// What the LEDs are showing right now, from `/json/live`.
+///
+/// Only for previews, WLED serves at most 256 (ESP8266) or 1024 (ESP32) LEDs here,
+/// so longer strips come back downsampled (see `n`).
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Live {
-    /// Not Done, broken, dont use
+    /// Colors of every `n`th LED, starting with LED 0.
+    /// WLED mixes the white channel into RGB and applies the master brightness before sending these,
+    /// so they're what the strip looks like, not the raw values.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub leds: Option<Vec<String>>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub leds: Option<Vec<Color>>,
 
-    /// Not Done, broken, dont use
+    /// 1 or more; downsampling factor, `leds` has every `n`th LED of the strip.
+    /// 1 if the whole strip fits.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub n: Option<i64>,
+    pub n: Option<u16>,
 }
 
 impl TryFrom<&str> for Live{
@@ -27,3 +36,56 @@ impl TryFrom<&str> for Live{
     }
 }
 
+impl TryInto<String> for &Live{
+    type Error = WledJsonApiError;
+    fn try_into(self) -> Result<String, WledJsonApiError> {
+        serde_json::to_string(self).map_err(|e| {WledJsonApiError::SerdeError(e)})
+    }
+}
+
+impl Live {
+    /// Stretches the (possibly downsampled) frame back out to `led_count` LEDs,
+    /// every physical LED gets the color of the sample it was skipped in favor of.
+    /// LEDs past the end of the frame come out black.
+    pub fn to_physical(&self, led_count: u16) -> Vec<Color> {
+        let leds = self.leds.as_deref().unwrap_or(&[]);
+        let n = self.n.unwrap_or(1).max(1) as usize;
+        (0..led_count as usize)
+            .map(|i| leds.get(i / n).copied().unwrap_or(Color::BLACK))
+            .collect()
+    }
+
+    /// `to_physical` with the LED count from `info.leds.count`, None if that isn't known
+    pub fn to_physical_from_info(&self, info: &Info) -> Option<Vec<Color>> {
+        let count = info.leds.as_ref()?.count?;
+        Some(self.to_physical(count))
+    }
+}
+
+/// WLED sends these as hex strings, so they go back out the same way
+fn serialize_hex<S: Serializer>(leds: &Option<Vec<Color>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match leds {
+        Some(l) => serializer.collect_seq(l.iter().map(|c| c.to_hex())),
+        None => serializer.serialize_none(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let live = Live::try_from(r#"{"leds":["FF0000","00FF00","0000FF"],"n":2}"#).unwrap();
+        assert_eq!(live.leds.as_ref().unwrap()[1], Color::rgb(0, 255, 0));
+        assert_eq!(live.to_physical(6), vec![
+            Color::rgb(255, 0, 0), Color::rgb(255, 0, 0),
+            Color::rgb(0, 255, 0), Color::rgb(0, 255, 0),
+            Color::rgb(0, 0, 255), Color::rgb(0, 0, 255),
+        ]);
+        assert_eq!(live.to_physical(7)[6], Color::BLACK);
+        let back: String = (&live).try_into().unwrap();
+        assert_eq!(back, r#"{"leds":["FF0000","00FF00","0000FF"],"n":2}"#);
+    }
+}
@@ -1,9 +1,15 @@
 pub mod state;
+pub mod color;
+pub mod relative;
+pub mod individual;
 pub mod info;
 pub mod palettes;
 pub mod effects;
 pub mod cfg;
 pub mod state_info;
+pub mod full;
+pub mod response;
+pub mod presets;
 pub mod nodes;
 pub mod net;
 pub mod live;
@@ -12,4 +18,22 @@ pub mod live;
 
 pub fn none_function<T>() -> Option<T>{
     None
-}
\ No newline at end of file
+}
+
+
+/// For fields WLED accepts as either a single value or an array of them (`"dur": 100` or `"dur": [100, 50]`).
+/// use with `#[serde(deserialize_with = "one_or_many")]`, always comes out as a Vec.
+pub fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where D: serde::Deserializer<'de>, T: serde::Deserialize<'de> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match <Option<OneOrMany<T>> as serde::Deserialize>::deserialize(deserializer)? {
+        Some(OneOrMany::One(t)) => Some(vec![t]),
+        Some(OneOrMany::Many(v)) => Some(v),
+        None => None,
+    })
+}
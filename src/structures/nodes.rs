@@ -1,5 +1,10 @@
+use std::fmt;
+use std::net::IpAddr;
+
+use reqwest::Url;
 use serde;
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::builder::WledBuilder;
 use crate::errors::WledJsonApiError;
 use crate::structures::none_function;
 
@@ -19,42 +24,219 @@ impl TryFrom<&str> for Nodes{
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(from = "RawNode", into = "RawNode")]
 pub struct Node {
     /// Not a trick question, really just the name of the corresponding node
-    #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(default = "none_function")]
     pub name: Option<String>,
 
     /// 'a waste of space as we only have 5 types'
     ///     - the WLED source code
     ///
-    /// #define NODE_TYPE_ID_UNDEFINED        0
-    /// #define NODE_TYPE_ID_ESP8266         82 // should be 1
-    /// #define NODE_TYPE_ID_ESP32           32 // should be 2
-    /// #define NODE_TYPE_ID_ESP32S2         33 // etc
-    /// #define NODE_TYPE_ID_ESP32S3         34
-    /// #define NODE_TYPE_ID_ESP32C3         35
-    ///
-    ///
-    /// Considering the above, this field may be changed by the time you are using this
+    /// What chip the node runs on, see `NodeType`
+    pub type_field: Option<NodeType>,
+
+    /// If the node is on. Newer builds send it as the top bit of `type`, older ones always look off
+    pub on: bool,
+
+    /// Not a trick question, really just IP address. None if the node sent something that isn't one
+    pub ip: Option<IpAddr>,
+
+    /// How many of this devices own discovery broadcasts went by since the node was last heard from.
+    /// 0 is fresh, WLED forgets nodes that stay quiet for too long
+    pub age: Option<u8>,
+
+    /// Build ID of the nodes firmware (YYMMDDB, B = daily build index), see `Node::build`
+    pub vid: Option<u32>,
+}
+
+/// `Node` the way WLED sends it, with the on bit still in `type`
+#[derive(Serialize, Deserialize)]
+struct RawNode {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "none_function")]
+    name: Option<String>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
     #[serde(rename = "type")]
-    pub type_field: Option<u8>,
+    type_field: Option<u8>,
 
-    /// Not a trick question, really just IP address
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub ip: Option<String>,
+    #[serde(deserialize_with = "ip_or_none")]
+    ip: Option<IpAddr>,
 
-    /// We have our top 4 engineers on the case, we'll know aht this is in about 3 years
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub age: Option<u8>,
+    age: Option<u8>,
 
-    /// We have our top 4 engineers on the case, we'll know aht this is in about 3 years
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub vid: Option<u32>,
+    vid: Option<u32>,
+}
+
+impl From<RawNode> for Node {
+    fn from(raw: RawNode) -> Self {
+        Node{
+            name: raw.name,
+            type_field: raw.type_field.map(NodeType::from),
+            on: raw.type_field.is_some_and(|t| t & 0x80 != 0),
+            ip: raw.ip,
+            age: raw.age,
+            vid: raw.vid,
+        }
+    }
+}
+
+impl From<Node> for RawNode {
+    fn from(node: Node) -> Self {
+        RawNode{
+            name: node.name,
+            type_field: node.type_field.map(|t| u8::from(t) | if node.on { 0x80 } else { 0 }),
+            ip: node.ip,
+            age: node.age,
+            vid: node.vid,
+        }
+    }
+}
+
+impl Node {
+    /// the date and index of the build the node runs, from `vid`
+    pub fn build(&self) -> Option<BuildVersion> {
+        self.vid.and_then(BuildVersion::from_vid)
+    }
+
+    /// `http://ip/`, WLEDs web server is always on port 80. `MissingKey` without an `ip`
+    pub fn url(&self) -> Result<Url, WledJsonApiError> {
+        let url = match self.ip.ok_or(WledJsonApiError::MissingKey)? {
+            IpAddr::V4(ip) => format!("http://{ip}/"),
+            IpAddr::V6(ip) => format!("http://[{ip}]/"),
+        };
+        Url::parse(&url).map_err(|_| WledJsonApiError::InvalidUrl(url))
+    }
+
+    /// a builder for the node, in case it needs more than the defaults (a shorter timeout, probably)
+    pub fn builder(&self) -> Result<WledBuilder, WledJsonApiError> {
+        Ok(WledBuilder::new(&self.url()?))
+    }
+}
+
+fn ip_or_none<'de, D>(deserializer: D) -> Result<Option<IpAddr>, D::Error>
+where D: Deserializer<'de> {
+    let ip: Option<String> = Option::deserialize(deserializer)?;
+    Ok(ip.and_then(|ip| ip.parse().ok()))
+}
+
+
+/// The chip a node runs on.
+/// Newer builds set the top bit of the type when the node is on, that bit is `Node::on` and ignored here.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "u8", into = "u8")]
+pub enum NodeType {
+    NODE_TYPE_ID_UNDEFINED,
+    NODE_TYPE_ID_ESP8266,
+    NODE_TYPE_ID_ESP32,
+    NODE_TYPE_ID_ESP32S2,
+    NODE_TYPE_ID_ESP32S3,
+    NODE_TYPE_ID_ESP32C3,
+    /// a type this library doesn't know about yet
+    NODE_TYPE_ID_UNKNOWN(u8),
+}
+
+impl From<u8> for NodeType {
+    fn from(id: u8) -> Self {
+        match id & 0x7F {
+            0 => NodeType::NODE_TYPE_ID_UNDEFINED,
+            82 => NodeType::NODE_TYPE_ID_ESP8266, // should be 1
+            32 => NodeType::NODE_TYPE_ID_ESP32,   // should be 2
+            33 => NodeType::NODE_TYPE_ID_ESP32S2, // etc
+            34 => NodeType::NODE_TYPE_ID_ESP32S3,
+            35 => NodeType::NODE_TYPE_ID_ESP32C3,
+            n => NodeType::NODE_TYPE_ID_UNKNOWN(n),
+        }
+    }
+}
+
+impl From<NodeType> for u8 {
+    fn from(t: NodeType) -> Self {
+        match t {
+            NodeType::NODE_TYPE_ID_UNDEFINED => 0,
+            NodeType::NODE_TYPE_ID_ESP8266 => 82,
+            NodeType::NODE_TYPE_ID_ESP32 => 32,
+            NodeType::NODE_TYPE_ID_ESP32S2 => 33,
+            NodeType::NODE_TYPE_ID_ESP32S3 => 34,
+            NodeType::NODE_TYPE_ID_ESP32C3 => 35,
+            NodeType::NODE_TYPE_ID_UNKNOWN(n) => n,
+        }
+    }
+}
+
+
+/// A build ID (`vid`) taken apart: `2310130` is the first build of 2023-10-13
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BuildVersion {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// which build of the day, from 0
+    pub build: u8,
+}
+
+impl BuildVersion {
+    /// None if `vid` isn't a YYMMDDB date
+    pub fn from_vid(vid: u32) -> Option<BuildVersion> {
+        let build = (vid % 10) as u8;
+        let day = (vid / 10 % 100) as u8;
+        let month = (vid / 1000 % 100) as u8;
+        let year = vid / 100000;
+        if year > 99 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        Some(BuildVersion{ year: 2000 + year as u16, month, day, build })
+    }
+
+    pub fn to_vid(&self) -> u32 {
+        (self.year as u32 % 100) * 100000 + self.month as u32 * 1000 + self.day as u32 * 10 + self.build as u32
+    }
+}
+
+impl fmt::Display for BuildVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} build {}", self.year, self.month, self.day, self.build)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let nodes = Nodes::try_from(r#"{"nodes":[
+            {"name":"Desk","type":32,"ip":"192.168.1.41","age":0,"vid":2310130},
+            {"name":"Porch","type":210,"ip":"192.168.1.42","age":2,"vid":2405180},
+            {"name":"Odd","type":99,"ip":"nope"}
+        ]}"#).unwrap();
+        let [desk, porch, odd] = &nodes.nodes[..] else { panic!("expected 3 nodes") };
+
+        assert_eq!(desk.type_field, Some(NodeType::NODE_TYPE_ID_ESP32));
+        assert_eq!(desk.url().unwrap().as_str(), "http://192.168.1.41/");
+        assert_eq!(desk.build(), Some(BuildVersion{ year: 2023, month: 10, day: 13, build: 0 }));
+        assert_eq!(desk.build().unwrap().to_vid(), 2310130);
+        assert_eq!(desk.build().unwrap().to_string(), "2023-10-13 build 0");
+        // 82 with the "on" bit set
+        assert_eq!(porch.type_field, Some(NodeType::NODE_TYPE_ID_ESP8266));
+        assert!(porch.on && !desk.on);
+        assert!(serde_json::to_string(porch).unwrap().contains(r#""type":210"#));
+        assert!(porch.build() > desk.build());
+        assert_eq!(odd.type_field, Some(NodeType::NODE_TYPE_ID_UNKNOWN(99)));
+        assert_eq!(odd.ip, None);
+        assert!(matches!(odd.url(), Err(WledJsonApiError::MissingKey)));
+        assert_eq!(BuildVersion::from_vid(12345), None);
+
+        let json = serde_json::to_string(desk).unwrap();
+        assert_eq!(json, r#"{"name":"Desk","type":32,"ip":"192.168.1.41","age":0,"vid":2310130}"#);
+    }
 }
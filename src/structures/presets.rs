@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use serde;
+use serde::{Serialize, Deserialize};
+use crate::errors::WledJsonApiError;
+use crate::structures::none_function;
+use crate::structures::state::{Playlist, State};
+
+
+/// Everything in `/presets.json`, keyed by preset ID (1 to 250).
+/// WLED keeps an empty `"0":{}` entry at the top, it's left in here.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presets(pub BTreeMap<u8, Preset>);
+
+impl TryFrom<&str> for Presets{
+    type Error = WledJsonApiError;
+    fn try_from(str_in: &str) -> Result<Presets, WledJsonApiError> {
+        serde_json::from_str(str_in).map_err(|e| {WledJsonApiError::SerdeError(e)})
+    }
+}
+
+impl Presets {
+    pub fn get(&self, id: u8) -> Option<&Preset> {
+        self.0.get(&id)
+    }
+
+    /// first preset with this name, if any
+    pub fn find_by_name(&self, name: &str) -> Option<(u8, &Preset)> {
+        self.0.iter().find(|(_, p)| p.name.as_deref() == Some(name)).map(|(id, p)| (*id, p))
+    }
+}
+
+
+/// One entry of `presets.json`. Either a state snapshot (the `state` fields),
+/// an API command string (`win`), or a playlist (`state.playlist`).
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    /// name of the preset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "none_function")]
+    #[serde(rename = "n")]
+    pub name: Option<String>,
+
+    /// quick load label, shown on the quick load buttons in the UI. 1 or 2 characters (or one emoji)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "none_function")]
+    pub ql: Option<String>,
+
+    /// HTTP API command string ("FX=5&SX=100"), present if the preset was saved as an API command
+    /// instead of a snapshot of the state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "none_function")]
+    pub win: Option<String>,
+
+    /// everything else; a state snapshot, or a JSON API command.
+    /// playlists are in here too, as `state.playlist`
+    #[serde(flatten)]
+    pub state: State,
+}
+
+impl Preset {
+    pub fn is_playlist(&self) -> bool {
+        self.state.playlist.is_some()
+    }
+
+    pub fn is_api_command(&self) -> bool {
+        self.win.is_some()
+    }
+
+    /// the `"0":{}` placeholder WLED puts at the top of the file
+    pub fn is_empty(&self) -> bool {
+        self == &Preset::default()
+    }
+}
+
+
+/// What gets POSTed to `json/state` to save a preset.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct PresetSave {
+    /// 1 to 250; ID to save to, overwrites whatever is there
+    pub psave: u8,
+
+    /// name of the preset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "n")]
+    pub name: Option<String>,
+
+    /// quick load label
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ql: Option<String>,
+
+    /// include brightness when saving the current state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ib: Option<bool>,
+
+    /// save segment bounds when saving the current state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sb: Option<bool>,
+
+    /// If true, the preset becomes whatever else is in this request (`state`/`win`)
+    /// instead of a snapshot of what the device is currently showing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub o: Option<bool>,
+
+    /// HTTP API command string to save, needs `o`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub win: Option<String>,
+
+    /// JSON API command to save, needs `o`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(flatten)]
+    pub state: Option<State>,
+}
+
+impl PresetSave {
+    /// snapshot of what the device is showing right now, brightness and segment bounds included
+    pub fn current_state(id: u8, name: &str) -> PresetSave {
+        PresetSave{
+            psave: id,
+            name: Some(name.to_string()),
+            ib: Some(true),
+            sb: Some(true),
+            ..Default::default()
+        }
+    }
+
+    /// saves `state` as the preset without applying it
+    pub fn from_state(id: u8, name: &str, state: &State) -> PresetSave {
+        PresetSave{
+            psave: id,
+            name: Some(name.to_string()),
+            o: Some(true),
+            state: Some(state.clone()),
+            ..Default::default()
+        }
+    }
+
+    /// saves `playlist` as a playlist preset, turning the light on when it's loaded
+    pub fn playlist(id: u8, name: &str, playlist: &Playlist) -> PresetSave {
+        PresetSave::from_state(id, name, &State{
+            on: Some(true),
+            playlist: Some(playlist.clone()),
+            ..Default::default()
+        })
+    }
+
+    /// saves an HTTP API command string ("FX=5&SX=100") as the preset
+    pub fn api_command(id: u8, name: &str, command: &str) -> PresetSave {
+        PresetSave{
+            psave: id,
+            name: Some(name.to_string()),
+            o: Some(true),
+            win: Some(command.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// the same preset saved again (under `name`), what a rename is in WLED
+    pub fn from_preset(id: u8, name: &str, preset: &Preset) -> PresetSave {
+        PresetSave{
+            psave: id,
+            name: Some(name.to_string()),
+            ql: preset.ql.clone(),
+            o: Some(true),
+            win: preset.win.clone(),
+            state: Some(preset.state.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_quick_load(mut self, ql: &str) -> PresetSave {
+        self.ql = Some(ql.to_string());
+        self
+    }
+}
+
+impl TryInto<String> for &PresetSave{
+    type Error = WledJsonApiError;
+    fn try_into(self) -> Result<String, WledJsonApiError> {
+        serde_json::to_string(self).map_err(|e| {WledJsonApiError::SerdeError(e)})
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let a: Presets = Presets::try_from(r#"{"0":{},"1":{"on":true,"bri":128,"transition":7,"mainseg":0,"seg":[{"id":0,"start":0,"stop":6,"fx":0}],"n":"Warm","ql":"W"},"2":{"n":"api","win":"FX=5&SX=100"},"3":{"playlist":{"ps":[1,2],"dur":[100,100],"transition":[7,7],"repeat":0,"end":0},"on":true,"n":"loop"}}"#).unwrap();
+        assert!(a.get(0).unwrap().is_empty());
+        assert_eq!(a.get(1).unwrap().state.bri, Some(128));
+        assert_eq!(a.get(1).unwrap().ql.as_deref(), Some("W"));
+        assert!(a.get(2).unwrap().is_api_command());
+        assert!(a.get(3).unwrap().is_playlist());
+        assert_eq!(a.find_by_name("loop").unwrap().0, 3);
+
+        let save: String = (&PresetSave::api_command(4, "fx", "FX=5")).try_into().unwrap();
+        assert_eq!(save, r#"{"psave":4,"n":"fx","o":true,"win":"FX=5"}"#);
+    }
+}
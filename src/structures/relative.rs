@@ -0,0 +1,163 @@
+use std::fmt;
+use std::str::FromStr;
+use serde;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use crate::errors::WledJsonApiError;
+
+
+/// A value WLED takes either as a plain number or as one of its relative string forms,
+/// used for `Seg::fx`, `Seg::sx`, `Seg::ix`, `Seg::pal` and `State::ps`.
+///
+/// State responses always have plain numbers in them, so what you read back is always `Absolute`.
+///
+/// ```
+/// use wled_json_api_library::structures::relative::RelativeValue;
+///
+/// assert_eq!(serde_json::to_string(&RelativeValue::<u8>::Increment(Some(10))).unwrap(), r#""~10""#);
+/// assert_eq!("1~17~".parse::<RelativeValue<i32>>().unwrap(), RelativeValue::cycle(1, 17));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelativeValue<T> {
+    /// plain number, sent as a number
+    Absolute(T),
+    /// "~" for +1 (wraps around at the end), "~10" for +10 (stops at the end)
+    Increment(Option<T>),
+    /// "~-" for -1 (wraps around at the start), "~-10" for -10 (stops at the start)
+    Decrement(Option<T>),
+    /// "r", a random value in the valid range
+    Random,
+    /// "1~17~"; steps through start to end (both included) every time it's sent.
+    /// WLED only does this for presets (`ps`)
+    Cycle{ start: T, end: T, step: CycleStep },
+}
+
+/// which way a `RelativeValue::Cycle` goes
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CycleStep {
+    /// "1~17~"
+    #[default]
+    Next,
+    /// "1~17~-"
+    Previous,
+    /// "1~17~r"
+    Random,
+}
+
+impl<T> RelativeValue<T> {
+    /// the value, if this is an absolute one
+    pub fn absolute(&self) -> Option<&T> {
+        match self {
+            RelativeValue::Absolute(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn cycle(start: T, end: T) -> RelativeValue<T> {
+        RelativeValue::Cycle{ start, end, step: CycleStep::Next }
+    }
+}
+
+impl<T> From<T> for RelativeValue<T> {
+    fn from(v: T) -> Self {
+        RelativeValue::Absolute(v)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for RelativeValue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelativeValue::Absolute(v) => write!(f, "{v}"),
+            RelativeValue::Increment(None) => f.write_str("~"),
+            RelativeValue::Increment(Some(by)) => write!(f, "~{by}"),
+            RelativeValue::Decrement(None) => f.write_str("~-"),
+            RelativeValue::Decrement(Some(by)) => write!(f, "~-{by}"),
+            RelativeValue::Random => f.write_str("r"),
+            RelativeValue::Cycle{ start, end, step } => {
+                let step = match step {
+                    CycleStep::Next => "",
+                    CycleStep::Previous => "-",
+                    CycleStep::Random => "r",
+                };
+                write!(f, "{start}~{end}~{step}")
+            }
+        }
+    }
+}
+
+impl<T: FromStr> FromStr for RelativeValue<T> {
+    type Err = WledJsonApiError;
+    fn from_str(s: &str) -> Result<RelativeValue<T>, WledJsonApiError> {
+        let invalid = || WledJsonApiError::InvalidValue(s.to_string());
+        let number = |n: &str| n.parse::<T>().map_err(|_| invalid());
+        let optional = |n: &str| if n.is_empty() { Ok(None) } else { number(n).map(Some) };
+
+        if s == "r" {
+            return Ok(RelativeValue::Random);
+        }
+        if let Some(rest) = s.strip_prefix('~') {
+            return match rest.strip_prefix('-') {
+                Some(by) => Ok(RelativeValue::Decrement(optional(by)?)),
+                None => Ok(RelativeValue::Increment(optional(rest)?)),
+            };
+        }
+        match s.split('~').collect::<Vec<&str>>()[..] {
+            [v] => Ok(RelativeValue::Absolute(number(v)?)),
+            [start, end, step] => Ok(RelativeValue::Cycle{
+                start: number(start)?,
+                end: number(end)?,
+                step: match step {
+                    "" => CycleStep::Next,
+                    "-" => CycleStep::Previous,
+                    "r" => CycleStep::Random,
+                    _ => return Err(invalid()),
+                },
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl<T: Serialize + fmt::Display> Serialize for RelativeValue<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            RelativeValue::Absolute(v) => v.serialize(serializer),
+            r => serializer.collect_str(r),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de> + FromStr> Deserialize<'de> for RelativeValue<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RelativeValue<T>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum NumberOrString<T> {
+            Number(T),
+            String(String),
+        }
+        match NumberOrString::<T>::deserialize(deserializer)? {
+            NumberOrString::Number(v) => Ok(RelativeValue::Absolute(v)),
+            NumberOrString::String(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        for s in ["~", "~-", "~10", "~-10", "r", "1~17~", "1~17~-", "3~9~r"] {
+            let v: RelativeValue<i32> = s.parse().unwrap();
+            assert_eq!(v.to_string(), s);
+            assert_eq!(serde_json::to_string(&v).unwrap(), format!("\"{s}\""));
+        }
+        assert_eq!(serde_json::from_str::<RelativeValue<u8>>("128").unwrap(), RelativeValue::Absolute(128));
+        assert_eq!(serde_json::from_str::<RelativeValue<u8>>(r#""~-""#).unwrap(), RelativeValue::Decrement(None));
+        assert_eq!(serde_json::to_string(&RelativeValue::Absolute(-1)).unwrap(), "-1");
+        assert!("~x".parse::<RelativeValue<u8>>().is_err());
+        assert!("1~2".parse::<RelativeValue<u8>>().is_err());
+        assert!("~300".parse::<RelativeValue<u8>>().is_err());
+    }
+}
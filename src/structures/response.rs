@@ -0,0 +1,141 @@
+use serde_json::Value;
+use crate::errors::WledJsonApiError;
+use crate::structures::state::State;
+
+
+/// What WLED answers a POST to `json/state` with, if it didn't answer with an error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlushResponse {
+    /// `{"success":true}`, what you get when `v` isn't set
+    Success,
+    /// the full state, what you get when `v` is true
+    State(State),
+}
+
+impl TryFrom<&str> for FlushResponse{
+    type Error = WledJsonApiError;
+    /// `{"error":n}` turns into `WledJsonApiError::WledError`
+    fn try_from(str_in: &str) -> Result<FlushResponse, WledJsonApiError> {
+        let value: Value = serde_json::from_str(str_in)?;
+        if let Some(e) = value.get("error") {
+            let code = e.as_u64().unwrap_or(0).min(u8::MAX as u64) as u8;
+            return Err(WledJsonApiError::WledError(WledErrorCode::from(code)));
+        }
+        match value.get("success") {
+            Some(Value::Bool(true)) => Ok(FlushResponse::Success),
+            Some(_) => Err(WledJsonApiError::UnexpectedResponse(str_in.to_string())),
+            None => Ok(FlushResponse::State(serde_json::from_value(value)?)),
+        }
+    }
+}
+
+impl FlushResponse {
+    /// Parses an answer that came back with `status`. Anything but a 2xx is an error: WLEDs own
+    /// `{"error":n}` if that's what the body is, `HttpError` otherwise (whatever else the body says).
+    pub fn from_http(status: reqwest::StatusCode, body: &str) -> Result<FlushResponse, WledJsonApiError> {
+        if status.is_success() {
+            return FlushResponse::try_from(body);
+        }
+        match FlushResponse::try_from(body) {
+            Err(e @ WledJsonApiError::WledError(_)) => Err(e),
+            _ => Err(WledJsonApiError::HttpError(status)),
+        }
+    }
+}
+
+
+/// Error codes WLED puts in `{"error":n}`. names are the ones from const.h in the WLED source (as of ~14.0)
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum WledErrorCode {
+    /// all good, should never actually show up in an error
+    ERR_NONE,
+    /// permission denied (settings PIN / OTA lock)
+    ERR_DENIED,
+    /// couldn't get the JSON buffer lock because another request was using it, try again
+    ERR_CONCURRENCY,
+    /// JSON buffer was too small for the request
+    ERR_NOBUF,
+    /// not implemented
+    ERR_NOT_IMPL,
+    /// JSON parsing failed (input too large?)
+    ERR_JSON,
+    /// could not init filesystem (no partition?)
+    ERR_FS_BEGIN,
+    /// the FS is full or the maximum file size is reached
+    ERR_FS_QUOTA,
+    /// failed to load preset (not there?)
+    ERR_FS_PLOAD,
+    /// failed to load IR JSON config
+    ERR_FS_IRLOAD,
+    /// failed to load remote JSON config
+    ERR_FS_RMLOAD,
+    /// general unspecified filesystem error
+    ERR_FS_GENERAL,
+    /// temperature sensor over the threshold
+    ERR_OVERTEMP,
+    /// current over the threshold (ABL)
+    ERR_OVERCURRENT,
+    /// voltage under the threshold
+    ERR_UNDERVOLT,
+    /// a code this library doesn't know about yet
+    ERR_UNKNOWN(u8),
+}
+
+impl From<u8> for WledErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0 => WledErrorCode::ERR_NONE,
+            1 => WledErrorCode::ERR_DENIED,
+            2 => WledErrorCode::ERR_CONCURRENCY,
+            3 => WledErrorCode::ERR_NOBUF,
+            4 => WledErrorCode::ERR_NOT_IMPL,
+            9 => WledErrorCode::ERR_JSON,
+            10 => WledErrorCode::ERR_FS_BEGIN,
+            11 => WledErrorCode::ERR_FS_QUOTA,
+            12 => WledErrorCode::ERR_FS_PLOAD,
+            13 => WledErrorCode::ERR_FS_IRLOAD,
+            14 => WledErrorCode::ERR_FS_RMLOAD,
+            19 => WledErrorCode::ERR_FS_GENERAL,
+            30 => WledErrorCode::ERR_OVERTEMP,
+            31 => WledErrorCode::ERR_OVERCURRENT,
+            32 => WledErrorCode::ERR_UNDERVOLT,
+            c => WledErrorCode::ERR_UNKNOWN(c),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        assert_eq!(FlushResponse::try_from(r#"{"success":true}"#).unwrap(), FlushResponse::Success);
+        match FlushResponse::try_from(r#"{"on":true,"bri":3}"#).unwrap() {
+            FlushResponse::State(s) => assert_eq!(s.bri, Some(3)),
+            o => panic!("expected a state, got {o:?}"),
+        }
+        assert!(matches!(
+            FlushResponse::try_from(r#"{"error":9}"#),
+            Err(WledJsonApiError::WledError(WledErrorCode::ERR_JSON))
+        ));
+        assert!(matches!(
+            FlushResponse::try_from(r#"{"error":77}"#),
+            Err(WledJsonApiError::WledError(WledErrorCode::ERR_UNKNOWN(77)))
+        ));
+
+        // a proxy's JSON error page would parse as an (empty) state, the status has to win
+        let bad_gateway = reqwest::StatusCode::BAD_GATEWAY;
+        assert!(matches!(
+            FlushResponse::from_http(bad_gateway, r#"{"message":"upstream down"}"#),
+            Err(WledJsonApiError::HttpError(s)) if s == bad_gateway
+        ));
+        assert!(matches!(
+            FlushResponse::from_http(reqwest::StatusCode::BAD_REQUEST, r#"{"error":9}"#),
+            Err(WledJsonApiError::WledError(WledErrorCode::ERR_JSON))
+        ));
+        assert!(FlushResponse::from_http(reqwest::StatusCode::OK, r#"{"success":true}"#).is_ok());
+    }
+}
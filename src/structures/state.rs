@@ -1,7 +1,10 @@
 use serde;
 use serde::{Serialize, Deserialize};
 use crate::errors::WledJsonApiError;
-use crate::structures::none_function;
+use crate::structures::color::SegColors;
+use crate::structures::relative::RelativeValue;
+use crate::structures::individual::IndividualLeds;
+use crate::structures::{none_function, one_or_many};
 
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -28,16 +31,17 @@ pub struct State {
     pub tt: Option<u8>,
 
     /// -1 to 65535; ID of currently set preset. 1~17~ can be used to iterate through presets 1-17.
+    /// see `RelativeValue` for the other forms
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub ps: Option<i32>,
+    pub ps: Option<RelativeValue<i32>>,
 
     /// 1 to 16 (250 in 0.11); Save current light config to specified preset slot. Not included in state response.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
     pub psave: Option<u8>,
 
-    /// -1 to 0; 	ID of currently set playlist. For now, this sets the preset cycle feature, -1 is off and 0 is on.
+    /// -1 to 0; ID of currently set playlist. For now, this sets the preset cycle feature, -1 is off and 0 is on.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
     pub pl: Option<i8>,
@@ -208,7 +212,7 @@ pub struct Seg {
     #[serde(default = "none_function")]
     pub spc: Option<u8>,
 
-    /// -len+1 to len; 	Offset (how many LEDs to rotate the virtual start of the segments, available since 0.13.0)
+    /// -len+1 to len; Offset (how many LEDs to rotate the virtual start of the segments, available since 0.13.0)
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
     pub of: Option<i16>,
@@ -248,30 +252,31 @@ pub struct Seg {
     #[serde(rename = "n")]
     pub name: Option<String>,
 
-    /// Array that has up to 3 color arrays as elements, the primary, secondary (background) and tertiary colors of the segment. Each color is an array of 3 or 4 bytes, which represent an RGB(W) color.
+    /// The primary, secondary (background) and tertiary colors of the segment, RGB(W).
+    /// sent as arrays or hex strings, see `SegColors::format`
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub col: Option<Vec<Vec<u8>>>,
+    pub col: Option<SegColors>,
 
     /// 0 to info.fxcount -1; ID of the effect or ~ to increment, ~- to decrement, or r for random.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub fx: Option<u16>,
+    pub fx: Option<RelativeValue<u16>>,
 
     /// Relative effect speed. ~ to increment, ~- to decrement. ~10 to increment by 10, ~-10 to decrement by 10.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub sx: Option<u8>,
+    pub sx: Option<RelativeValue<u8>>,
 
     /// Effect intensity. ~ to increment, ~- to decrement. ~10 to increment by 10, ~-10 to decrement by 10.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub ix: Option<u8>,
+    pub ix: Option<RelativeValue<u8>>,
 
     /// 0 to info.palcount -1; ID of the color palette or ~ to increment, ~- to decrement, or r for random.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub pal: Option<u16>,
+    pub pal: Option<RelativeValue<u16>>,
 
     /// Effect custom slider 1. Custom sliders are hidden or displayed and labeled based on effect metadata.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -327,6 +332,12 @@ pub struct Seg {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
     pub m12: Option<u8>,
+
+    /// Individual LED control, colors for single LEDs or ranges of them. Not included in state response.
+    /// setting this freezes the segment. for a whole frame see `IndividualLeds::chunked`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "none_function")]
+    pub i: Option<IndividualLeds>,
 }
 
 
@@ -338,17 +349,19 @@ pub struct Playlist {
     /// Array of preset ID integers to be applied in this order.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub ps: Option<Vec<u8>>,
+    pub ps: Option<Vec<u16>>,
 
     /// Array of time each preset should be kept, in tenths of seconds. If only one integer is supplied, all presets will be kept for that time.Defaults to 10 seconds if not provided.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
+    #[serde(deserialize_with = "one_or_many")]
     pub dur: Option<Vec<u32>>,
 
     /// Array of time each preset should transition to the next one, in tenths of seconds. If only one integer is supplied, all presets will transition for that time. Defaults to the current transition time if not provided.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub transition: Option<u8>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub transition: Option<Vec<u16>>,
 
     /// How many times the entire playlist should cycle before finishing. Set to 0 for an indefinite cycle. Default to indefinite if not provided.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -358,13 +371,120 @@ pub struct Playlist {
     /// Single preset ID to apply after the playlist finished. Has no effect when an indefinite cycle is set. If not provided, the light will stay on the last preset of the playlist.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default = "none_function")]
-    pub end: Option<u8>,
+    pub end: Option<u16>,
+
+    /// Shuffle; if true the order of the presets is randomized every cycle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default = "none_function")]
+    pub r: Option<bool>,
+}
+
+impl Playlist {
+    pub fn builder() -> PlaylistBuilder {
+        PlaylistBuilder::default()
+    }
+}
+
+
+/// Builds a `Playlist` and checks that `dur` and `transition` line up with `ps` before you send it.
+///
+/// ```
+/// use wled_json_api_library::structures::state::Playlist;
+///
+/// let playlist = Playlist::builder()
+///     .entry(1, 300, 7)   // preset 1 for 30 s, 700 ms transition into it
+///     .entry(2, 600, 20)
+///     .shuffle(true)
+///     .repeat(3)
+///     .end(5)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct PlaylistBuilder {
+    ps: Vec<u16>,
+    dur: Vec<u32>,
+    transition: Vec<u16>,
+    repeat: Option<u16>,
+    end: Option<u16>,
+    shuffle: Option<bool>,
+}
+
+impl PlaylistBuilder {
+    /// adds a preset with its own duration and transition, both in tenths of seconds
+    pub fn entry(mut self, preset: u16, dur: u32, transition: u16) -> Self {
+        self.ps.push(preset);
+        self.dur.push(dur);
+        self.transition.push(transition);
+        self
+    }
+
+    /// sets the whole `ps` array at once
+    pub fn presets(mut self, ps: Vec<u16>) -> Self {
+        self.ps = ps;
+        self
+    }
+
+    /// one value for every preset, or one per preset. empty leaves it up to WLED (10 s)
+    pub fn durations(mut self, dur: Vec<u32>) -> Self {
+        self.dur = dur;
+        self
+    }
+
+    /// one value for every preset, or one per preset. empty leaves it up to WLED (current transition time)
+    pub fn transitions(mut self, transition: Vec<u16>) -> Self {
+        self.transition = transition;
+        self
+    }
+
+    /// 0 cycles forever
+    pub fn repeat(mut self, repeat: u16) -> Self {
+        self.repeat = Some(repeat);
+        self
+    }
+
+    /// preset to apply once the playlist is done
+    pub fn end(mut self, preset: u16) -> Self {
+        self.end = Some(preset);
+        self
+    }
+
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = Some(shuffle);
+        self
+    }
+
+    pub fn build(self) -> Result<Playlist, WledJsonApiError> {
+        if self.ps.is_empty() {
+            return Err(WledJsonApiError::InvalidPlaylist("a playlist needs at least one preset".to_string()));
+        }
+        if self.ps.contains(&0) || self.end == Some(0) {
+            return Err(WledJsonApiError::InvalidPlaylist("preset IDs start at 1".to_string()));
+        }
+        let len = self.ps.len();
+        if !matches!(self.dur.len(), 0 | 1) && self.dur.len() != len {
+            return Err(WledJsonApiError::InvalidPlaylist(
+                format!("{} durations for {len} presets, needs 1 or {len}", self.dur.len())));
+        }
+        if !matches!(self.transition.len(), 0 | 1) && self.transition.len() != len {
+            return Err(WledJsonApiError::InvalidPlaylist(
+                format!("{} transitions for {len} presets, needs 1 or {len}", self.transition.len())));
+        }
+        Ok(Playlist{
+            ps: Some(self.ps),
+            dur: if self.dur.is_empty() { None } else { Some(self.dur) },
+            transition: if self.transition.is_empty() { None } else { Some(self.transition) },
+            repeat: self.repeat,
+            end: self.end,
+            r: self.shuffle,
+        })
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::structures::state::State;
+    use crate::structures::state::{Playlist, State};
 
     #[test]
     fn it_works() {
@@ -374,7 +494,22 @@ mod tests {
         println!("State object: {:?}", a);
         let b: String = a.try_into().unwrap();
         println!("converted object: {:?}", b);
+        assert!(b.contains(r#""col":[[255,160,0],[0,0,0],[0,0,0]]"#));
 
+    }
 
+    #[test]
+    fn playlist() {
+        let p = Playlist::builder().entry(300, 100, 7).entry(2, 50, 0).shuffle(true).end(4).build().unwrap();
+        assert_eq!(serde_json::to_string(&p).unwrap(), r#"{"ps":[300,2],"dur":[100,50],"transition":[7,0],"end":4,"r":true}"#);
+
+        assert!(Playlist::builder().presets(vec![1, 2, 3]).durations(vec![10, 20]).build().is_err());
+        assert!(Playlist::builder().presets(vec![1, 2, 3]).durations(vec![10]).transitions(vec![1, 2, 3]).build().is_ok());
+        assert!(Playlist::builder().build().is_err());
+
+        // WLED takes a single number as well as an array
+        let single: Playlist = serde_json::from_str(r#"{"ps":[1,2],"dur":100,"transition":7}"#).unwrap();
+        assert_eq!(single.dur, Some(vec![100]));
+        assert_eq!(single.transition, Some(vec![7]));
     }
 }
@@ -0,0 +1,362 @@
+use std::net::TcpStream;
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::HeaderMap;
+use reqwest::Url;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::{http, Message, WebSocket};
+
+use crate::errors::WledJsonApiError;
+use crate::structures::color::Color;
+use crate::structures::live::Live;
+use crate::structures::response::FlushResponse;
+use crate::structures::state::State;
+use crate::structures::state_info::StateInfo;
+use crate::wled::Wled;
+
+
+/// Something WLED pushed down the websocket
+#[derive(Debug, Clone, PartialEq)]
+pub enum WsEvent {
+    /// full `{state, info}`, sent once right after connecting and again on every change
+    StateInfo(Box<StateInfo>),
+    /// `{"success":true}`, the answer to a state patch that didn't ask for `v`
+    Success,
+}
+
+
+/// A session on WLEDs `/ws` websocket.
+///
+/// WLED pushes the whole state + info whenever anything changes (no matter who changed it),
+/// so this is the way to keep up with a device without polling.
+/// Patches sent through `send_state` are handled exactly like a POST to `json/state`.
+#[derive(Debug)]
+pub struct WledWebSocket {
+    pub socket: WebSocket<TcpStream>,
+}
+
+impl WledWebSocket{
+
+    /// `url` is the same base url you would hand to `Wled`, `ws` gets joined onto it.
+    /// Only plain `http` (or `ws`) urls, there's no TLS here so `https` is an `InvalidUrl`
+    pub fn connect(url: &Url, connect_timeout: Duration) -> Result<WledWebSocket, WledJsonApiError> {
+        WledWebSocket::connect_with_headers(url, connect_timeout, &HeaderMap::new())
+    }
+
+    /// `connect`, with `headers` added to the handshake (auth for a reverse proxy, say)
+    pub fn connect_with_headers(url: &Url, connect_timeout: Duration, headers: &HeaderMap) -> Result<WledWebSocket, WledJsonApiError> {
+        if !matches!(url.scheme(), "http" | "ws") {
+            return Err(WledJsonApiError::InvalidUrl(url.to_string()));
+        }
+        let mut ws_url = url.join("ws").map_err(|_|{WledJsonApiError::InvalidUrl(url.to_string())})?;
+        ws_url.set_scheme("ws").map_err(|_|{WledJsonApiError::InvalidUrl(url.to_string())})?;
+
+        let addr = ws_url.socket_addrs(|| Some(80))
+            .map_err(|_|{WledJsonApiError::InvalidUrl(url.to_string())})?
+            .into_iter()
+            .next()
+            .ok_or_else(||{WledJsonApiError::InvalidUrl(url.to_string())})?;
+        let stream = TcpStream::connect_timeout(&addr, connect_timeout)?;
+        stream.set_nodelay(true)?;
+
+        let mut request = ws_url.as_str().into_client_request()?;
+        // tungstenite is on a newer `http` than reqwest, so the headers go over by hand
+        for (name, value) in headers {
+            let name = http::HeaderName::from_bytes(name.as_str().as_bytes())
+                .map_err(|_| WledJsonApiError::InvalidValue(format!("header {name}")))?;
+            let value = http::HeaderValue::from_bytes(value.as_bytes())
+                .map_err(|_| WledJsonApiError::InvalidValue(format!("value of header {name}")))?;
+            request.headers_mut().append(name, value);
+        }
+        let (socket, _) = tungstenite::client(request, stream)
+            .map_err(|e| match e {
+                tungstenite::HandshakeError::Failure(e) => WledJsonApiError::from(e),
+                tungstenite::HandshakeError::Interrupted(_) => WledJsonApiError::WebSocketClosed,
+            })?;
+        Ok(WledWebSocket{ socket })
+    }
+
+    /// how long `read_event` waits before giving up with an io `WouldBlock`/`TimedOut`. None waits forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<(), WledJsonApiError> {
+        Ok(self.socket.get_ref().set_read_timeout(timeout)?)
+    }
+
+    /// sends a state patch, same thing as a POST to `json/state`
+    pub fn send_state(&mut self, state: &State) -> Result<(), WledJsonApiError> {
+        let packet: String = state.try_into()?;
+        self.send_text(packet)
+    }
+
+    /// asks WLED to push the current state + info, even though nothing changed
+    pub fn request_state_info(&mut self) -> Result<(), WledJsonApiError> {
+        self.send_text(r#"{"v":true}"#.to_string())
+    }
+
+    pub(crate) fn send_text(&mut self, text: String) -> Result<(), WledJsonApiError> {
+        Ok(self.socket.send(Message::text(text))?)
+    }
+
+    /// Blocks until WLED sends something this library understands.
+    /// pings are answered along the way, `{"error":n}` comes back as `WledJsonApiError::WledError`.
+    pub fn read_event(&mut self) -> Result<WsEvent, WledJsonApiError> {
+        loop {
+            match self.socket.read()? {
+                Message::Text(t) => return parse_text(t.as_str()),
+                // keep reading after a close so tungstenite gets to send the reply,
+                // the read after that ends with ConnectionClosed
+                _ => continue,
+            }
+        }
+    }
+
+    /// iterator over `read_event`, ends when the socket is closed
+    pub fn events(&mut self) -> Events<'_> {
+        Events{ ws: self }
+    }
+
+    /// Turns on the live view for this session, see `LiveView`.
+    /// It's turned off again when the `LiveView` is dropped.
+    pub fn live_view(&mut self) -> Result<LiveView<'_>, WledJsonApiError> {
+        self.send_text(r#"{"lv":true}"#.to_string())?;
+        Ok(LiveView{ ws: self })
+    }
+
+    pub fn close(mut self) -> Result<(), WledJsonApiError> {
+        self.socket.close(None)?;
+        // drain until the close handshake is done
+        loop {
+            match self.socket.read() {
+                Ok(_) => continue,
+                Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn parse_text(text: &str) -> Result<WsEvent, WledJsonApiError> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    if value.get("state").is_some() {
+        return Ok(WsEvent::StateInfo(Box::new(serde_json::from_value(value)?)));
+    }
+    match FlushResponse::try_from(text)? {
+        FlushResponse::Success => Ok(WsEvent::Success),
+        FlushResponse::State(_) => Err(WledJsonApiError::UnexpectedResponse(text.to_string())),
+    }
+}
+
+
+pub struct Events<'a> {
+    ws: &'a mut WledWebSocket,
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<WsEvent, WledJsonApiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.ws.read_event() {
+            Err(WledJsonApiError::WebSocketClosed) => None,
+            r => Some(r),
+        }
+    }
+}
+
+
+/// One frame of the live view
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveFrame {
+    /// when this library got the frame, WLED doesn't timestamp them
+    pub received: SystemTime,
+
+    /// Colors of the strip, white mixed in and master brightness applied.
+    /// Long strips (and big matrices) are downsampled, like `/json/live`.
+    pub leds: Vec<Color>,
+
+    /// width and height for 2D setups, after downsampling. `leds` is row by row
+    pub size: Option<(u8, u8)>,
+
+    /// downsampling factor, only known for frames sent as JSON by older WLEDs (see `Live::n`)
+    pub n: Option<u16>,
+}
+
+impl LiveFrame {
+    /// Binary frame: 'L', version, then RGB triples.
+    /// version 1 has them right after, version 2 (2D) puts width and height first.
+    fn from_binary(data: &[u8]) -> Result<LiveFrame, WledJsonApiError> {
+        let (size, rgb) = match data {
+            [b'L', 1, rgb @ ..] => (None, rgb),
+            [b'L', 2, w, h, rgb @ ..] => (Some((*w, *h)), rgb),
+            _ => return Err(WledJsonApiError::UnexpectedResponse(format!("{data:?}"))),
+        };
+        Ok(LiveFrame{
+            received: SystemTime::now(),
+            leds: rgb.chunks_exact(3).map(|c| Color::rgb(c[0], c[1], c[2])).collect(),
+            size,
+            n: None,
+        })
+    }
+
+    fn from_live(live: Live) -> LiveFrame {
+        LiveFrame{
+            received: SystemTime::now(),
+            leds: live.leds.unwrap_or_default(),
+            size: None,
+            n: live.n,
+        }
+    }
+}
+
+
+/// Live view on a `WledWebSocket`, made by `WledWebSocket::live_view`.
+///
+/// WLED streams what the LEDs show (about every 40 ms) for as long as it's on.
+/// State pushes that come in between are skipped. Dropping this sends `{"lv":false}`.
+/// WLED only streams to one client at a time, turning it on here turns it off for the others.
+pub struct LiveView<'a> {
+    ws: &'a mut WledWebSocket,
+}
+
+impl LiveView<'_> {
+    /// blocks until the next frame, same timeout rules as `read_event`
+    pub fn next_frame(&mut self) -> Result<LiveFrame, WledJsonApiError> {
+        loop {
+            match self.ws.socket.read()? {
+                Message::Binary(b) => return LiveFrame::from_binary(&b),
+                // older WLEDs send the frame the same way as /json/live
+                Message::Text(t) if t.as_str().contains(r#""leds""#) => {
+                    return Ok(LiveFrame::from_live(Live::try_from(t.as_str())?));
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+impl Iterator for LiveView<'_> {
+    type Item = Result<LiveFrame, WledJsonApiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Err(WledJsonApiError::WebSocketClosed) => None,
+            r => Some(r),
+        }
+    }
+}
+
+impl Drop for LiveView<'_> {
+    fn drop(&mut self) {
+        // nothing to be done about it if the socket is already gone
+        let _ = self.ws.send_text(r#"{"lv":false}"#.to_string());
+    }
+}
+
+
+impl Wled{
+    /// opens a websocket to the same WLED, with the connect timeout and headers this handle was built with
+    pub fn open_websocket(&self) -> Result<WledWebSocket, WledJsonApiError> {
+        WledWebSocket::connect_with_headers(&self.url, self.timeouts.connect, &self.headers)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn it_works() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            ws.send(Message::text(r#"{"state":{"on":true,"bri":128},"info":{"ver":"0.14.0"}}"#)).unwrap();
+            let patch = ws.read().unwrap();
+            ws.send(Message::text(r#"{"success":true}"#)).unwrap();
+            ws.send(Message::text(r#"{"state":{"on":false,"bri":128},"info":{"ver":"0.14.0"}}"#)).unwrap();
+            ws.close(None).unwrap();
+            while ws.read().is_ok() {}
+            patch.into_text().unwrap().to_string()
+        });
+
+        let url = Url::parse(&format!("http://{addr}/")).unwrap();
+        let mut ws = WledWebSocket::connect(&url, Duration::from_secs(1)).unwrap();
+        ws.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let first = ws.read_event().unwrap();
+        assert!(matches!(&first, WsEvent::StateInfo(si) if si.state.on == Some(true)));
+        ws.send_state(&State { on: Some(false), ..Default::default() }).unwrap();
+
+        let rest: Vec<WsEvent> = ws.events().map(|e| e.unwrap()).collect();
+        assert_eq!(rest[0], WsEvent::Success);
+        assert!(matches!(&rest[1], WsEvent::StateInfo(si) if si.state.on == Some(false)));
+        assert_eq!(rest.len(), 2);
+
+        assert_eq!(server.join().unwrap(), r#"{"on":false}"#);
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)] // the callback signature is tungstenite's
+    fn handshake_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut seen = None;
+            let ws = tungstenite::accept_hdr(stream, |req: &tungstenite::handshake::server::Request, resp| {
+                let header = |n: &str| req.headers().get(n).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+                seen = Some((header("x-token"), header("user-agent")));
+                Ok(resp)
+            }).unwrap();
+            drop(ws);
+            seen.unwrap()
+        });
+
+        let wled = Wled::builder(&Url::parse(&format!("http://{addr}/")).unwrap())
+            .header(reqwest::header::HeaderName::from_static("x-token"), reqwest::header::HeaderValue::from_static("hunter2"))
+            .user_agent("fixture-controller")
+            .probe(false)
+            .build()
+            .unwrap();
+        drop(wled.open_websocket().unwrap());
+        assert_eq!(server.join().unwrap(), (Some("hunter2".to_string()), Some("fixture-controller".to_string())));
+
+        // no TLS here, so no quietly talking plaintext to port 80 either
+        let https = Url::parse("https://wled.example.com/").unwrap();
+        assert!(matches!(WledWebSocket::connect(&https, Duration::from_secs(1)), Err(WledJsonApiError::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn live_view() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept(stream).unwrap();
+            let on = ws.read().unwrap().into_text().unwrap().to_string();
+            ws.send(Message::text(r#"{"state":{"on":true},"info":{}}"#)).unwrap();
+            ws.send(Message::binary(vec![b'L', 1, 255, 0, 0, 0, 255, 0])).unwrap();
+            ws.send(Message::binary(vec![b'L', 2, 2, 1, 1, 2, 3, 4, 5, 6])).unwrap();
+            ws.send(Message::text(r#"{"leds":["0000FF"],"n":4}"#)).unwrap();
+            let off = ws.read().unwrap().into_text().unwrap().to_string();
+            ws.close(None).unwrap();
+            while ws.read().is_ok() {}
+            (on, off)
+        });
+
+        let url = Url::parse(&format!("http://{addr}/")).unwrap();
+        let mut ws = WledWebSocket::connect(&url, Duration::from_secs(1)).unwrap();
+        ws.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let frames: Vec<LiveFrame> = ws.live_view().unwrap().take(3).map(|f| f.unwrap()).collect();
+        assert_eq!(frames[0].leds, vec![Color::rgb(255, 0, 0), Color::rgb(0, 255, 0)]);
+        assert_eq!(frames[1].size, Some((2, 1)));
+        assert_eq!(frames[1].leds[1], Color::rgb(4, 5, 6));
+        assert_eq!(frames[2].n, Some(4));
+        ws.close().unwrap();
+
+        assert_eq!(server.join().unwrap(), (r#"{"lv":true}"#.to_string(), r#"{"lv":false}"#.to_string()));
+    }
+}
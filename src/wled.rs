@@ -1,18 +1,25 @@
-use std::time::Duration;
-
 use reqwest;
-use reqwest::blocking::{Client, ClientBuilder, Response};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::HeaderMap;
 use reqwest::Url;
 
+use crate::builder::{join_endpoint, Timeouts, WledBuilder};
 use crate::errors::WledJsonApiError;
 use crate::structures::cfg::Cfg;
 use crate::structures::effects::Effects;
 use crate::structures::info::Info;
 use crate::structures::live::Live;
 use crate::structures::net::Net;
-use crate::structures::nodes::Nodes;
+use crate::structures::nodes::{Node, Nodes};
 use crate::structures::palettes::Palettes;
-use crate::structures::state::State;
+use crate::structures::state::{Playlist, Seg, State};
+use crate::structures::state_info::StateInfo;
+use crate::structures::full::Full;
+use crate::structures::response::FlushResponse;
+use crate::structures::presets::{Presets, PresetSave};
+use crate::structures::relative::RelativeValue;
+use crate::structures::color::Color;
+use crate::structures::individual::{IndividualLeds, DEFAULT_LEDS_PER_REQUEST};
 
 
 
@@ -26,42 +33,37 @@ pub struct Wled {
     pub live: Option<Live>,
     pub nodes: Option<Nodes>,
     pub net: Option<Net>,
+    pub presets: Option<Presets>,
     pub client: Client, // should probably be private in most cases, but fuck you
+    /// base url of the WLED (including any reverse proxy path), always ends in '/'.
     pub url: Url,
+    /// per request class timeouts, see `WledBuilder`
+    pub timeouts: Timeouts,
+    /// extra headers from `WledBuilder` (user agent included), the client already sends them,
+    /// this is for the websocket handshake
+    pub headers: HeaderMap,
 }
 
 impl Wled{
 
-
+    /// shorthand for `WledBuilder::new(url).build()`: 5000 ms timeouts, gzip,
+    /// and a GET of `json/cfg` to make sure something is there.
     pub fn try_from_url(url: &Url) -> Result<Wled, WledJsonApiError> {
-        let temp_client: Client = ClientBuilder::new()
-            .gzip(true)
-            .timeout(Duration::from_millis(5000u64))
-            .build()
-            .map_err(|e|{WledJsonApiError::ReqwestError(e)})?;
-        let mut temp_url: Url = url.clone();
-        temp_url.set_path("json/cfg");
-        match temp_client
-            .get(temp_url.clone())
-            .send() {
-            Ok(a) if a.status() == reqwest::StatusCode::OK => {
-                Ok(Wled{
-                    effects: None,
-                    palettes: None,
-                    state: None,
-                    info: None,
-                    cfg: None,
-                    live: None,
-                    nodes: None,
-                    net: None,
-                    client: temp_client,
-                    url: temp_url,
-                })
-            }
-            Ok(o) => {Err(WledJsonApiError::HttpError(o.status()))}
-            Err(e) => {Err(WledJsonApiError::ReqwestError(e))}
-        }
+        WledBuilder::new(url).build()
+    }
+
+    /// `try_from_url` on a node from another WLEDs `json/nodes`, see `Node::url`
+    pub fn try_from_node(node: &Node) -> Result<Wled, WledJsonApiError> {
+        Wled::try_from_url(&node.url()?)
+    }
+
+    pub fn builder(url: &Url) -> WledBuilder {
+        WledBuilder::new(url)
+    }
 
+    /// full url of an endpoint like "json/state", taking the base path into account
+    pub fn endpoint(&self, path: &str) -> Result<Url, WledJsonApiError> {
+        join_endpoint(&self.url, path)
     }
 
 
@@ -70,14 +72,48 @@ impl Wled{
         match &self.state{
             Some(s) => {
                 let packet: String = s.try_into()?;
+                Ok(self.client
+                    .post(self.endpoint("json/state")?)
+                    .timeout(self.timeouts.flush)
+                    .body(packet)
+                    .send()?)
+            }
+            None => Err(WledJsonApiError::FlushNone)
+        }
+    }
 
-                let mut temp_url = self.url.clone();
-                temp_url.set_path("json/state");
 
-                Ok(self.client.post(temp_url).body(packet).send()?)
+    /// Like `flush_state`, but sets `v` so WLED answers with the full state it ended up in,
+    /// and stores that in `self.state`. WLED error payloads come back as `WledJsonApiError::WledError`.
+    ///
+    /// `v` itself is only set on what's sent, the cached state gets replaced by WLEDs answer anyway.
+    pub fn flush_state_verbose(&mut self) -> Result<FlushResponse, WledJsonApiError> {
+        let packet: String = match &self.state{
+            Some(s) => {
+                let mut verbose = s.clone();
+                verbose.v = Some(true);
+                (&verbose).try_into()?
             }
-            None => Err(WledJsonApiError::FlushNone)
+            None => return Err(WledJsonApiError::FlushNone)
+        };
+        let response = self.post_state(packet)?;
+        if let FlushResponse::State(s) = &response {
+            self.state = Some(s.clone());
         }
+        Ok(response)
+    }
+
+    /// POSTs an already serialized packet to `json/state` and parses what comes back
+    pub(crate) fn post_state(&self, packet: String) -> Result<FlushResponse, WledJsonApiError> {
+        let response = self.client
+            .post(self.endpoint("json/state")?)
+            .timeout(self.timeouts.flush)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(packet)
+            .send()?;
+        let status = response.status();
+        let text = response.text()?;
+        FlushResponse::from_http(status, &text)
     }
 
 
@@ -88,117 +124,152 @@ impl Wled{
         match &self.cfg{
             Some(s) => {
                 let packet: String = s.try_into()?;
-
-                let mut temp_url = self.url.clone();
-                temp_url.set_path("json/cfg");
-
-                Ok(self.client.post(temp_url).body(packet).send()?)
+                Ok(self.client
+                    .post(self.endpoint("json/cfg")?)
+                    .timeout(self.timeouts.flush)
+                    .body(packet)
+                    .send()?)
             }
             None => Err(WledJsonApiError::FlushNone)
         }
     }
 
+    /// GETs `path` and hands back the body text, shared by all the `get_*_from_wled` below
+    fn get_text(&self, path: &str) -> Result<String, WledJsonApiError> {
+        Ok(self.client
+            .get(self.endpoint(path)?)
+            .timeout(self.timeouts.fetch)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()?
+            .text()?)
+    }
+
     pub fn get_effects_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/eff");
-        self.effects = Some(Effects::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.effects = Some(Effects::try_from(&*self.get_text("json/eff")?)?);
         Ok(())
     }
 
     pub fn get_info_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/info");
-        self.info = Some(Info::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.info = Some(Info::try_from(&*self.get_text("json/info")?)?);
         Ok(())
     }
 
     pub fn get_state_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/state");
-        self.state = Some(State::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.state = Some(State::try_from(&*self.get_text("json/state")?)?);
         Ok(())
     }
 
     pub fn get_cfg_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/cfg");
-        self.cfg = Some(Cfg::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.cfg = Some(Cfg::try_from(&*self.get_text("json/cfg")?)?);
         Ok(())
     }
 
     pub fn get_net_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/net");
-        self.net = Some(Net::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.net = Some(Net::try_from(&*self.get_text("json/net")?)?);
         Ok(())
     }
 
     pub fn get_nodes_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/nodes");
-        self.nodes = Some(Nodes::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.nodes = Some(Nodes::try_from(&*self.get_text("json/nodes")?)?);
         Ok(())
     }
 
     pub fn get_palettes_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/pal");
-        self.palettes = Some(Palettes::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.palettes = Some(Palettes::try_from(&*self.get_text("json/pal")?)?);
+        Ok(())
+    }
+
+    /// fills `state` and `info` from `/json/si`, one request instead of two
+    pub fn get_state_info_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        let state_info = StateInfo::try_from(&*self.get_text("json/si")?)?;
+        self.state = Some(state_info.state);
+        self.info = Some(state_info.info);
+        Ok(())
+    }
+
+    /// fills `state`, `info`, `effects` and `palettes` from `/json`, one request instead of four
+    pub fn get_full_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        let full = Full::try_from(&*self.get_text("json")?)?;
+        self.state = Some(full.state);
+        self.info = Some(full.info);
+        self.effects = Some(full.effects);
+        self.palettes = Some(full.palettes);
         Ok(())
     }
 
     pub fn get_live_from_wled(&mut self) -> Result<(), WledJsonApiError> {
-        let mut temp_url = self.url.clone();
-        temp_url.set_path("json/live");
-        self.live = Some(Live::try_from(
-            &*self.client
-                .get(temp_url)
-                .header(reqwest::header::ACCEPT, "application/json")
-                .send()?
-                .text()?
-        )?);
+        self.live = Some(Live::try_from(&*self.get_text("json/live")?)?);
+        Ok(())
+    }
+
+    /// downloads `/presets.json` into `self.presets`
+    pub fn get_presets_from_wled(&mut self) -> Result<(), WledJsonApiError> {
+        self.presets = Some(Presets::try_from(&*self.get_text("presets.json")?)?);
+        Ok(())
+    }
+
+    /// Saves (or overwrites) a preset, see the constructors on `PresetSave`.
+    /// WLED writes the file in the background, so `presets.json` can lag behind for a moment.
+    pub fn save_preset(&self, save: &PresetSave) -> Result<FlushResponse, WledJsonApiError> {
+        self.post_state(save.try_into()?)
+    }
+
+    /// WLED can't rename in place, so this saves the preset again under the new name.
+    /// Uses `self.presets`, fetching it first if it's None.
+    pub fn rename_preset(&mut self, id: u8, name: &str) -> Result<FlushResponse, WledJsonApiError> {
+        if self.presets.is_none() {
+            self.get_presets_from_wled()?;
+        }
+        let preset = self.presets.as_ref()
+            .and_then(|p| p.get(id))
+            .ok_or(WledJsonApiError::MissingKey)?;
+        let save = PresetSave::from_preset(id, name, preset);
+        let response = self.save_preset(&save)?;
+        if let Some(p) = self.presets.as_mut().and_then(|p| p.0.get_mut(&id)) {
+            p.name = Some(name.to_string());
+        }
+        Ok(response)
+    }
+
+    pub fn delete_preset(&mut self, id: u8) -> Result<FlushResponse, WledJsonApiError> {
+        let response = self.post_state(serde_json::json!({"pdel": id}).to_string())?;
+        if let Some(p) = self.presets.as_mut() {
+            p.0.remove(&id);
+        }
+        Ok(response)
+    }
+
+    pub fn apply_preset(&self, id: u8) -> Result<FlushResponse, WledJsonApiError> {
+        let state = State{ps: Some(RelativeValue::Absolute(id as i32)), ..Default::default()};
+        self.post_state((&state).try_into()?)
+    }
+
+    /// starts `playlist` right away, without saving it anywhere
+    pub fn apply_playlist(&self, playlist: &Playlist) -> Result<FlushResponse, WledJsonApiError> {
+        let state = State{playlist: Some(playlist.clone()), ..Default::default()};
+        self.post_state((&state).try_into()?)
+    }
+
+    /// saves `playlist` as preset `id`, apply it later with `apply_preset`
+    pub fn save_playlist_preset(&self, id: u8, name: &str, playlist: &Playlist) -> Result<FlushResponse, WledJsonApiError> {
+        self.save_preset(&PresetSave::playlist(id, name, playlist))
+    }
+
+    /// Sets every LED of segment `seg_id` to `colors` (LED 0 of the segment first) through the `i` array.
+    /// Long frames are split into several requests of `DEFAULT_LEDS_PER_REQUEST` LEDs so WLEDs JSON buffer
+    /// doesn't overflow, use `set_individual_leds_chunked` to pick the size yourself.
+    pub fn set_individual_leds(&self, seg_id: u8, colors: &[Color]) -> Result<(), WledJsonApiError> {
+        self.set_individual_leds_chunked(seg_id, colors, DEFAULT_LEDS_PER_REQUEST)
+    }
+
+    pub fn set_individual_leds_chunked(&self, seg_id: u8, colors: &[Color], per_request: usize) -> Result<(), WledJsonApiError> {
+        for chunk in IndividualLeds::chunked(0, colors, per_request)? {
+            let state = State{
+                seg: Some(vec![Seg{id: Some(seg_id as i16), i: Some(chunk.hex()), ..Default::default()}]),
+                ..Default::default()
+            };
+            self.post_state((&state).try_into()?)?;
+        }
         Ok(())
     }
 }
@@ -206,10 +277,114 @@ impl Wled{
 
 #[cfg(test)]
 mod tests {
-    use crate::structures::cfg::cfg_def::Def;
+    use crate::test_server::TestServer;
     use super::*;
 
     #[test]
+    fn against_stub() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/json/cfg" => (200, "{}".to_string()),
+            "/json/state" if r.method == "GET" => (200, r#"{"on":true,"bri":77}"#.to_string()),
+            "/json/state" => (200, r#"{"success":true}"#.to_string()),
+            _ => (404, String::new()),
+        });
+
+        let mut wled = Wled::try_from_url(&server.url()).unwrap();
+        wled.get_state_from_wled().unwrap();
+        assert_eq!(wled.state.as_ref().unwrap().bri, Some(77));
+
+        wled.state = Some(State { on: Some(false), ..Default::default() });
+        assert!(wled.flush_state().unwrap().status().is_success());
+        let posted = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        assert_eq!(posted.body, r#"{"on":false}"#);
+    }
+
+    #[test]
+    fn verbose_flush_updates_state() {
+        let server = TestServer::start(|r| match r.body.as_str() {
+            r#"{"bri":5,"v":true}"# => (200, r#"{"on":true,"bri":5,"transition":7}"#.to_string()),
+            _ => (400, r#"{"error":9}"#.to_string()),
+        });
+        let mut wled = Wled::builder(&server.url()).probe(false).build().unwrap();
+
+        wled.state = Some(State { bri: Some(5), ..Default::default() });
+        let response = wled.flush_state_verbose().unwrap();
+        assert!(matches!(response, FlushResponse::State(_)));
+        assert_eq!(wled.state.as_ref().unwrap().transition, Some(7));
+        assert_eq!(wled.state.as_ref().unwrap().v, None);
+
+        wled.state = Some(State { bri: Some(6), ..Default::default() });
+        assert!(matches!(
+            wled.flush_state_verbose(),
+            Err(WledJsonApiError::WledError(crate::structures::response::WledErrorCode::ERR_JSON))
+        ));
+    }
+
+    #[test]
+    fn presets() {
+        let server = TestServer::start(|r| match (r.method.as_str(), r.path.as_str()) {
+            ("GET", "/presets.json") => (200, r#"{"0":{},"3":{"n":"old","ql":"O","on":true,"bri":40}}"#.to_string()),
+            ("POST", "/json/state") => (200, r#"{"success":true}"#.to_string()),
+            _ => (404, String::new()),
+        });
+        let mut wled = Wled::builder(&server.url()).probe(false).build().unwrap();
+
+        wled.rename_preset(3, "new").unwrap();
+        wled.apply_preset(3).unwrap();
+        wled.delete_preset(3).unwrap();
+        wled.save_preset(&PresetSave::current_state(5, "now")).unwrap();
+        let playlist = Playlist::builder().presets(vec![1, 2]).durations(vec![100]).build().unwrap();
+        wled.save_playlist_preset(6, "pl", &playlist).unwrap();
+
+        let bodies: Vec<String> = server.requests().into_iter().filter(|r| r.method == "POST").map(|r| r.body).collect();
+        assert_eq!(bodies, vec![
+            r#"{"psave":3,"n":"new","ql":"O","o":true,"on":true,"bri":40}"#,
+            r#"{"ps":3}"#,
+            r#"{"pdel":3}"#,
+            r#"{"psave":5,"n":"now","ib":true,"sb":true}"#,
+            r#"{"psave":6,"n":"pl","o":true,"on":true,"playlist":{"ps":[1,2],"dur":[100]}}"#,
+        ]);
+        assert!(wled.presets.unwrap().get(3).is_none());
+    }
+
+    #[test]
+    fn individual_leds() {
+        let server = TestServer::start(|_| (200, r#"{"success":true}"#.to_string()));
+        let wled = Wled::builder(&server.url()).probe(false).build().unwrap();
+
+        let frame = [Color::rgb(255, 0, 0), Color::rgb(0, 255, 0), Color::rgb(0, 0, 255)];
+        wled.set_individual_leds_chunked(1, &frame, 2).unwrap();
+
+        let bodies: Vec<String> = server.requests().into_iter().map(|r| r.body).collect();
+        assert_eq!(bodies, vec![
+            r#"{"seg":[{"id":1,"i":[0,"FF0000","00FF00"]}]}"#,
+            r#"{"seg":[{"id":1,"i":[2,"0000FF"]}]}"#,
+        ]);
+    }
+
+    #[test]
+    fn full_in_one_request() {
+        let server = TestServer::start(|r| match r.path.as_str() {
+            "/json" => (200, r#"{"state":{"on":true,"bri":10},"info":{"ver":"0.14.0"},"effects":["Solid","Blink"],"palettes":["Default"]}"#.to_string()),
+            "/json/si" => (200, r#"{"state":{"on":false},"info":{"ver":"0.14.1"}}"#.to_string()),
+            _ => (404, String::new()),
+        });
+        let mut wled = Wled::builder(&server.url()).probe(false).build().unwrap();
+
+        wled.get_full_from_wled().unwrap();
+        assert_eq!(server.requests().len(), 1);
+        assert_eq!(wled.state.as_ref().unwrap().bri, Some(10));
+        assert_eq!(wled.info.as_ref().unwrap().ver.as_deref(), Some("0.14.0"));
+        assert!(wled.effects.is_some() && wled.palettes.is_some());
+
+        wled.get_state_info_from_wled().unwrap();
+        assert_eq!(server.requests().len(), 2);
+        assert_eq!(wled.state.as_ref().unwrap().on, Some(false));
+        assert_eq!(wled.info.as_ref().unwrap().ver.as_deref(), Some("0.14.1"));
+    }
+
+    #[test]
+    #[ignore = "needs a real WLED at 192.168.1.40"]
     fn it_works() {
         // create the URL
         let url: Url = Url::try_from("http://192.168.1.40/").unwrap();